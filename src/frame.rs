@@ -0,0 +1,122 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Framed whack streams
+//!
+//! A whack stream does not record how large its output is, so a
+//! file is cut into blocks and each block is framed by a header of
+//! its uncompressed size, its stored size, both big-endian `u32`,
+//! and its [`Encoding`], as in a venti clump. Blocks that do not
+//! compress are stored plain. The stream ends with the input.
+use std::io::{ErrorKind, Read, Write};
+
+use crate::unwhack::unwhack;
+use crate::venti::clump::{Encoding, MAX_LUMP_SIZE};
+use crate::venti::{u32get, u32put};
+use crate::whack::{Stats, whack, whackinit};
+
+/// size of a frame header
+pub const FRAME_HEADER_SIZE: usize = 9;
+/// largest block, as in venti
+pub const MAX_BLOCK_SIZE: usize = MAX_LUMP_SIZE;
+
+/// Compress `r` into a framed stream on `w`
+///
+/// Cuts the input into blocks of `block_size` bytes, compresses
+/// each at `level` as with [`whackinit`] and adds the statistics
+/// of the blocks that compressed to `stats`. Returns the number of
+/// bytes read and written.
+///
+/// # Errors
+///
+/// If the block size is out of range or reading or writing fails
+pub fn compress<R: Read, W: Write>(
+    r: &mut R,
+    w: &mut W,
+    level: u8,
+    block_size: usize,
+    stats: &mut Stats,
+) -> Result<(u64, u64), String> {
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(format!("bad block size {block_size}"));
+    }
+    let (mut nin, mut nout) = (0, 0);
+    let mut block = Vec::with_capacity(block_size);
+    loop {
+        block.clear();
+        r.by_ref()
+            .take(block_size as u64)
+            .read_to_end(&mut block)
+            .map_err(|e| format!("read: {e}"))?;
+        if block.is_empty() {
+            break;
+        }
+        let compressed = whack(&mut whackinit(level), &block, stats);
+        let (encoding, stored) = match &compressed {
+            Some(c) => (Encoding::Compressed, c.as_slice()),
+            None => (Encoding::None, block.as_slice()),
+        };
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        u32put(&mut header, block.len() as u32);
+        u32put(&mut header[4..], stored.len() as u32);
+        header[8] = encoding as u8;
+        w.write_all(&header)
+            .and_then(|_| w.write_all(stored))
+            .map_err(|e| format!("write: {e}"))?;
+        nin += block.len() as u64;
+        nout += (FRAME_HEADER_SIZE + stored.len()) as u64;
+        if block.len() < block_size {
+            break;
+        }
+    }
+    Ok((nin, nout))
+}
+
+/// Decompress the framed stream in `r` onto `w`
+///
+/// Returns the number of bytes written.
+///
+/// # Errors
+///
+/// If a frame is damaged or cut short, or reading or writing fails
+pub fn decompress<R: Read, W: Write>(r: &mut R, w: &mut W) -> Result<u64, String> {
+    let mut nout = 0;
+    let mut stored = Vec::new();
+    let mut n = 0;
+    loop {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        match r.read(&mut header[..1]) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("read: {e}")),
+        }
+        r.read_exact(&mut header[1..])
+            .map_err(|e| format!("frame {n}: header: {e}"))?;
+        let size = u32get(&header) as usize;
+        let len = u32get(&header[4..]) as usize;
+        if size > MAX_BLOCK_SIZE || len > size {
+            return Err(format!("frame {n}: bad sizes {len}/{size}"));
+        }
+        stored.resize(len, 0);
+        r.read_exact(&mut stored)
+            .map_err(|e| format!("frame {n}: data: {e}"))?;
+        let data = match header[8] {
+            e if e == Encoding::None as u8 && len == size => stored.clone(),
+            e if e == Encoding::Compressed as u8 => {
+                let data = unwhack(&stored, size).map_err(|e| format!("frame {n}: {e}"))?;
+                if data.len() != size {
+                    return Err(format!("frame {n}: {} bytes, expected {size}", data.len()));
+                }
+                data
+            }
+            e => {
+                return Err(format!(
+                    "frame {n}: bad encoding {e} for sizes {len}/{size}"
+                ));
+            }
+        };
+        w.write_all(&data).map_err(|e| format!("write: {e}"))?;
+        nout += size as u64;
+        n += 1;
+    }
+    Ok(nout)
+}
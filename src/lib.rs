@@ -5,13 +5,29 @@
 //! Use the `unwhack` function to decompress, and `whackblock` to compress.
 //! A `whack` function also exists if you want to control some parameters
 //! of compression, or want to collect statistics.
+//!
+//! The `venti` module reads and writes the venti structures that
+//! hold whack compressed blocks.
+//!
+//! The `plan9whack` binary compresses and decompresses files in the
+//! framed format of the `frame` module, and inspects whack streams
+//! and venti arena partitions.
 // Copyright 2024-2026 by Michael Stroucken
 mod constants;
+pub mod frame;
+mod sha1;
 mod testdata;
 pub mod unwhack;
+pub mod venti;
 pub mod whack;
 
 #[cfg(test)]
+// the original tests predate these lints
+#[allow(
+    clippy::needless_borrow,
+    clippy::needless_return,
+    clippy::unnecessary_unwrap
+)]
 mod tests {
     use base64::Engine;
     use base64::engine::general_purpose;
@@ -177,4 +193,1916 @@ mod tests {
             Err(rv.err().unwrap())
         }
     }
+
+    #[test]
+    /// test if prefix decompression stops after the requested bytes
+    pub fn unwhack_prefix_test() -> Result<(), String> {
+        let src = general_purpose::STANDARD
+            .decode(large_compressed_data())
+            .unwrap();
+        let target = general_purpose::STANDARD
+            .decode(large_uncompressed_data())
+            .unwrap();
+        for n in [0, 1, 100, 333, target.len()] {
+            let result = unwhack::unwhack_prefix(&src, n)?;
+            if result != target[..n] {
+                return Err(format!("prefix of {n} bytes doesn't match ground truth"));
+            }
+        }
+        // asking for more than the block holds returns the whole block
+        let result = unwhack::unwhack_prefix(&src, target.len() + 100)?;
+        if result != target {
+            return Err(String::from("oversized prefix doesn't match ground truth"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if a prefix ending inside a match is cut at the right byte
+    pub fn unwhack_prefix_match() -> Result<(), String> {
+        let src = general_purpose::STANDARD
+            .decode(compressed_65k_0bits())
+            .unwrap();
+        let result = unwhack::unwhack_prefix(&src, 1000)?;
+        if result != [0u8; 1000] {
+            return Err(String::from("prefix doesn't match ground truth"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if salvaging keeps the output decoded before an error
+    pub fn unwhack_salvage_short() -> Result<(), String> {
+        let src = general_purpose::STANDARD
+            .decode(large_compressed_data())
+            .unwrap();
+        let target = general_purpose::STANDARD
+            .decode(large_uncompressed_data())
+            .unwrap();
+        let rv = unwhack::unwhack_salvage(&src, target.len(), false);
+        if rv.error.is_some() || rv.data != target {
+            return Err(String::from("intact data was not fully recovered"));
+        }
+        let rv = unwhack::unwhack_salvage(&src, 500, false);
+        let error = rv.error.ok_or("error not reported")?;
+        if rv.data.is_empty() || rv.data.len() > 500 || rv.data != target[..rv.data.len()] {
+            return Err(String::from("salvaged data doesn't match ground truth"));
+        }
+        if error.bit_position == 0 || error.bit_position >= src.len() * 8 {
+            return Err(format!("implausible error position {}", error.bit_position));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if salvaging substitutes zeros for unresolvable matches
+    pub fn unwhack_salvage_zero_fill() -> Result<(), String> {
+        // a match of 3 bytes at offset 1 followed by the literal 'A'
+        let src = [0x80u8, 0x04, 0x10];
+        let rv = unwhack::unwhack_salvage(&src, 4, false);
+        match rv.error {
+            Some(e) if e.bit_position == 0 && rv.data.is_empty() => (),
+            _ => return Err(String::from("bad match not reported")),
+        }
+        let rv = unwhack::unwhack_salvage(&src, 4, true);
+        if rv.error.is_some() || rv.zeroed.len() != 1 || rv.data != b"\0\0\0A" {
+            return Err(format!("unexpected salvage result {:?}", rv.data));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if the bundled SHA-1 matches known digests
+    pub fn sha1_known() -> Result<(), String> {
+        let cases: [(&[u8], &str); 3] = [
+            (b"", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            (b"abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            ),
+        ];
+        for (data, hex) in cases {
+            let mut s = sha1::Sha1::new();
+            // feed in odd pieces to exercise the block buffer
+            for chunk in data.chunks(7) {
+                s.update(chunk);
+            }
+            let digest: String = s.finalize().iter().map(|b| format!("{b:02x}")).collect();
+            if digest != hex {
+                return Err(format!("digest {digest} should be {hex}"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if verifying a stream digests the same bytes unwhack produces
+    pub fn unwhack_verify_test() -> Result<(), String> {
+        for (compressed, n) in [
+            (large_compressed_data(), 0),
+            (compressed_65k_0bits(), 65536),
+            (compressed_512_countup(), 512),
+        ] {
+            let src = general_purpose::STANDARD.decode(compressed).unwrap();
+            let n = if n == 0 {
+                general_purpose::STANDARD
+                    .decode(large_uncompressed_data())
+                    .unwrap()
+                    .len()
+            } else {
+                n
+            };
+            let target = unwhack::unwhack(&src, n)?;
+            let rv = unwhack::unwhack_verify(&src, n)?;
+            let mut s = sha1::Sha1::new();
+            s.update(&target);
+            if rv.len != target.len() || rv.sha1 != s.finalize() {
+                return Err(String::from("verification digest doesn't match output"));
+            }
+        }
+        let src = general_purpose::STANDARD
+            .decode(compressed_65k_0bits())
+            .unwrap();
+        if unwhack::unwhack_verify(&src, 1000).is_ok() {
+            return Err(String::from("oversized output not detected"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if feeding a stream in pieces gives the one-shot result
+    pub fn decoder_pieces() -> Result<(), String> {
+        let src = general_purpose::STANDARD
+            .decode(large_compressed_data())
+            .unwrap();
+        let target = general_purpose::STANDARD
+            .decode(large_uncompressed_data())
+            .unwrap();
+        for size in [1, 2, 3, 5, 8, 13, 100, src.len()] {
+            let mut decoder = unwhack::Decoder::new(target.len());
+            for piece in src.chunks(size) {
+                let progress = decoder.feed(piece)?;
+                if progress.produced != decoder.output().len() {
+                    return Err(String::from("progress doesn't match output"));
+                }
+            }
+            if decoder.finish()? != target {
+                return Err(format!("pieces of {size} bytes don't match ground truth"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if the incremental decoder fails where unwhack fails
+    pub fn decoder_errors() -> Result<(), String> {
+        let src = general_purpose::STANDARD
+            .decode(compressed_65k_0bits())
+            .unwrap();
+        let expected = unwhack::unwhack(&src, 1000).err();
+        let mut decoder = unwhack::Decoder::new(1000);
+        let mut rv = Ok(());
+        for piece in src.chunks(3) {
+            if let Err(e) = decoder.feed(piece) {
+                rv = Err(e);
+                break;
+            }
+        }
+        let rv = match rv {
+            Ok(()) => decoder.finish().err(),
+            Err(e) => Some(e),
+        };
+        if rv != expected {
+            return Err(format!("decoder reported {rv:?}, unwhack {expected:?}"));
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn new_stats() -> whack::Stats {
+        whack::Stats {
+            statbytes: 0,
+            statoutbytes: 0,
+            statlits: 0,
+            statmatches: 0,
+            statlitbits: 0,
+            statoffbits: 0,
+            statlenbits: 0,
+        }
+    }
+
+    #[cfg(test)]
+    /// text-like pseudo random data, compressible but without long repeats
+    fn text_data(n: usize) -> Vec<u8> {
+        let mut x: u32 = 1;
+        let mut v = Vec::with_capacity(n);
+        for _ in 0..n {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            v.push(b"abcdefgh  \n"[(x >> 16) as usize % 11]);
+        }
+        v
+    }
+
+    #[test]
+    /// test if blocks larger than 32k round trip once dictionary positions wrap
+    pub fn whack_wrapped_offsets() -> Result<(), String> {
+        // positions start at 32k, so they wrap 32k into a block
+        for n in [32768, 32769, 40000, 60000] {
+            let src = text_data(n);
+            let rv =
+                whack::whackblock(&src).ok_or(format!("block of {n} bytes did not compress"))?;
+            if unwhack::unwhack(&rv, n)? != src {
+                return Err(format!("block of {n} bytes does not match source"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if levels outside MIN_LEVEL..=MAX_LEVEL compress as the bounds do
+    pub fn level_bounds() -> Result<(), String> {
+        use whack::{MAX_LEVEL, MIN_LEVEL, whack, whackinit};
+        let src = text_data(20000);
+        let mut stats = new_stats();
+        for (level, bound) in [(0, MIN_LEVEL), (MAX_LEVEL + 1, MAX_LEVEL)] {
+            let out = whack(&mut whackinit(level), &src, &mut stats);
+            if out.is_none() || out != whack(&mut whackinit(bound), &src, &mut stats) {
+                return Err(format!("level {level} differs from level {bound}"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if feeding the encoder in pieces gives the one-shot result
+    pub fn encoder_pieces() -> Result<(), String> {
+        let blocks = [
+            general_purpose::STANDARD
+                .decode(large_uncompressed_data())
+                .unwrap(),
+            general_purpose::STANDARD.decode(random_data()).unwrap(),
+            [0u8; 65536].to_vec(),
+            text_data(60000),
+            b"foofoofoo".to_vec(),
+            b"fo".to_vec(),
+        ];
+        for src in blocks {
+            for level in [1, 6, 10] {
+                let mut stats = new_stats();
+                let expected = whack::whack(&mut whack::whackinit(level), &src, &mut stats);
+                for size in [1, 7, 1000, src.len().max(1)] {
+                    let mut estats = new_stats();
+                    let mut encoder = whack::Encoder::new(level);
+                    for piece in src.chunks(size) {
+                        encoder.write(piece);
+                    }
+                    if encoder.finish(&mut estats) != expected {
+                        return Err(format!(
+                            "{} bytes at level {level} in pieces of {size} differ",
+                            src.len()
+                        ));
+                    }
+                    if estats.statlits != stats.statlits
+                        || estats.statoutbytes != stats.statoutbytes
+                    {
+                        return Err(String::from("statistics differ"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if clumps pick the right encoding and decode to their block
+    pub fn clump_round_trip() -> Result<(), String> {
+        use venti::clump::*;
+        let text = general_purpose::STANDARD
+            .decode(large_uncompressed_data())
+            .unwrap();
+        let random = general_purpose::STANDARD.decode(random_data()).unwrap();
+        for (data, encoding) in [
+            (text, Encoding::Compressed),
+            (random, Encoding::None),
+            (Vec::new(), Encoding::None),
+        ] {
+            let buf = encode(8, &data, CLUMP_MAGIC, 7, 1234)?;
+            let (clump, decoded) = decode(&buf)?;
+            if clump.encoding != encoding || decoded != data {
+                return Err(format!("{} byte block did not round trip", data.len()));
+            }
+            if clump.magic != CLUMP_MAGIC
+                || clump.info.kind != 8
+                || clump.creator != 7
+                || clump.time != 1234
+                || clump.info.score != venti::score::Score::of(&data)
+                || buf.len() != CLUMP_SIZE + clump.info.size as usize
+            {
+                return Err(String::from("clump header did not round trip"));
+            }
+            if decode(&buf[..buf.len() - 1]).is_ok() && !data.is_empty() {
+                return Err(String::from("short clump not detected"));
+            }
+        }
+        if encode(0, &[0; MAX_LUMP_SIZE + 1], CLUMP_MAGIC, 0, 0).is_ok() {
+            return Err(String::from("oversized block accepted"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if scores format, parse and verify blocks
+    pub fn score_verify() -> Result<(), String> {
+        use venti::score::*;
+        if Score::ZERO != Score::of(b"")
+            || Score::ZERO.to_string() != "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        {
+            return Err(String::from("zero score is wrong"));
+        }
+        let text = general_purpose::STANDARD
+            .decode(large_uncompressed_data())
+            .unwrap();
+        let src = general_purpose::STANDARD
+            .decode(large_compressed_data())
+            .unwrap();
+        let score: Score = Score::of(&text).to_string().parse()?;
+        if unwhack_verified(&src, text.len(), &score).map_err(|e| e.to_string())? != text {
+            return Err(String::from("verified data doesn't match ground truth"));
+        }
+        match unwhack_verified(&src, text.len(), &Score::ZERO) {
+            Err(ReadError::Integrity { expected, actual })
+                if expected == Score::ZERO && actual == score => {}
+            _ => return Err(String::from("score mismatch not detected")),
+        }
+        match unwhack_verified(&src, 10, &score) {
+            Err(ReadError::Decode(_)) => {}
+            _ => return Err(String::from("decode error not reported")),
+        }
+        for bad in ["", "da39", "xa39a3ee5e6b4b0d3255bfef95601890afd80709"] {
+            if bad.parse::<Score>().is_ok() {
+                return Err(format!("parsed bad score {bad:?}"));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    /// lay out an arena partition holding one arena with `blocks`
+    fn build_partition(blocks: &[(u8, Vec<u8>)], seal: bool) -> Result<Vec<u8>, String> {
+        use venti::arena::*;
+        let blocksize = 8192u32;
+        let mut part = ArenaPart {
+            version: ARENA_PART_VERSION,
+            blocksize,
+            arenabase: 0,
+        };
+        part.arenabase = (part.tabbase() + blocksize as u64) as u32;
+        let (start, stop) = (
+            part.arenabase as u64,
+            (part.arenabase + 32 * blocksize) as u64,
+        );
+        let mut image = vec![0u8; start as usize];
+        part.pack(&mut image[PART_BLANK as usize..]);
+        let map = format_amap(&[AMap {
+            name: String::from("arena0"),
+            start,
+            stop,
+        }]);
+        let tabbase = part.tabbase() as usize;
+        image[tabbase..tabbase + map.len()].copy_from_slice(&map);
+        let mut aw = ArenaWriter::new(
+            std::io::Cursor::new(image),
+            start,
+            "arena0",
+            stop - start,
+            blocksize,
+            0x12345678,
+            1,
+        )?;
+        for (kind, data) in blocks {
+            aw.write(*kind, data, 2)?;
+        }
+        let (_, image) = aw.finish(seal)?;
+        Ok(image.into_inner())
+    }
+
+    #[test]
+    /// test if clump headers match the directory without decoding
+    pub fn arena_clump_headers() -> Result<(), String> {
+        use venti::arena::*;
+        use venti::clump::{CLUMP_SIZE, Encoding};
+        let blocks = vec![(0, text_data(10000)), (16, b"ab".to_vec())];
+        let image = build_partition(&blocks, false)?;
+        let mut part = Partition::open(std::io::Cursor::new(image))?;
+        let arena = part.arena(0)?;
+        let clumps = part.clumps(&arena)?;
+        let encodings: Vec<Encoding> = clumps
+            .iter()
+            .map(|c| part.read_clump_header(&arena, c.addr))
+            .map(|h| h.map(|h| h.encoding))
+            .collect::<Result<_, _>>()?;
+        if encodings != [Encoding::Compressed, Encoding::None] {
+            return Err(format!("clumps encoded {encodings:?}"));
+        }
+        if part.read_clump_header(&arena, clumps[0].addr + 1).is_ok() {
+            return Err(String::from("header read at a bad address"));
+        }
+
+        // a plain data clump as venti writes it, laid out by hand
+        let mut clump = vec![0xd1, 0x5c, 0xb1, 0x0c, 13, 0, 2, 0, 2];
+        clump.extend_from_slice(&venti::score::Score::of(b"ab").0);
+        clump.extend_from_slice(&[1, 0, 0, 0, 7, 0, 0, 0, 9]);
+        clump.extend_from_slice(b"ab");
+        let (c, data) = venti::clump::decode(&clump)?;
+        if c.encoding != Encoding::None || c.info.kind != 0 || c.creator != 7 || data != b"ab" {
+            return Err(format!("clump read as {c:?}"));
+        }
+        let mut packed = [0u8; CLUMP_SIZE];
+        c.pack(&mut packed);
+        if packed[..] != clump[..CLUMP_SIZE] {
+            return Err(format!("clump packed as {packed:x?}"));
+        }
+        clump[29] = 2;
+        let compressed = venti::clump::Clump::unpack(&clump)?;
+        clump[29] = 0;
+        if compressed.encoding != Encoding::Compressed || venti::clump::decode(&clump).is_ok() {
+            return Err(String::from("encodings not those of venti"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if block types are stored and sent as the old venti types
+    pub fn disk_types() -> Result<(), String> {
+        use venti::block::*;
+        use venti::proto::*;
+        use venti::score::Score;
+        use venti::store::BlockStore;
+        // vttodisktype and vtfromdisktype, transcribed from libventi
+        let todisk = [13, 3, 4, 5, 6, 7, 8, 9, 2, 3, 4, 5, 6, 7, 8, 9, 1];
+        for (kind, &disk) in todisk.iter().enumerate() {
+            if to_disk_type(kind as u8) != disk {
+                return Err(format!(
+                    "type {kind} stored as {}",
+                    to_disk_type(kind as u8)
+                ));
+            }
+        }
+        let fromdisk = [
+            0xff, 16, 8, 9, 10, 11, 12, 13, 14, 15, 0xff, 0xff, 0xff, 0, 0xff,
+        ];
+        for (disk, &kind) in fromdisk.iter().enumerate() {
+            if from_disk_type(disk as u8) != kind {
+                return Err(format!(
+                    "old type {disk} read as {}",
+                    from_disk_type(disk as u8)
+                ));
+            }
+        }
+        if to_disk_type(17) != CORRUPT_TYPE || !same_type(1, 9) || same_type(1, 2) {
+            return Err(String::from("pointer types not folded"));
+        }
+
+        // a Tread of a data block as libventi packs it, laid out by hand
+        let score = Score::of(b"ab");
+        let mut tread = vec![VT_TREAD, 7];
+        tread.extend_from_slice(&score.0);
+        tread.extend_from_slice(&[13, 0, 0x20, 0x00]);
+        let f = Fcall::unpack(&tread)?;
+        let want = Msg::Tread {
+            score,
+            kind: DATA_TYPE,
+            count: 8192,
+        };
+        if f.msg != want || f.pack()? != tread {
+            return Err(format!("Tread read as {:?}", f.msg));
+        }
+
+        // a root clump is listed as old type 1 and found as a root
+        let image = build_partition(&[(ROOT_TYPE, b"ab".to_vec())], false)?;
+        let mut part = venti::arena::Partition::open(std::io::Cursor::new(image.clone()))?;
+        let arena = part.arena(0)?;
+        let off = arena.clumpinfo_offset(0) as usize;
+        let mut info = vec![1, 0, 2, 0, 2];
+        info.extend_from_slice(&score.0);
+        if image[off..off + info.len()] != info[..] {
+            return Err(format!("directory entry is {:x?}", &image[off..off + 25]));
+        }
+        let mut store = venti::store::ArenaStore::open(part)?;
+        if store.get(&score, ROOT_TYPE)?.as_deref() != Some(&b"ab"[..]) {
+            return Err(String::from("root clump not found"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if all blocks can be read back from an arena partition
+    pub fn arena_partition_read() -> Result<(), String> {
+        let text = general_purpose::STANDARD
+            .decode(large_uncompressed_data())
+            .unwrap();
+        let random = general_purpose::STANDARD.decode(random_data()).unwrap();
+        let blocks = vec![(0, text), (8, random), (16, b"foofoofoo".to_vec())];
+        let image = build_partition(&blocks, false)?;
+        let mut part = venti::arena::Partition::open(std::io::Cursor::new(image))?;
+        if part.map.len() != 1 || part.map[0].name != "arena0" {
+            return Err(String::from("arena map not read"));
+        }
+        let arena = part.arena(0)?;
+        if arena.trailer.diskstats.clumps != 3 || arena.trailer.diskstats.cclumps != 2 {
+            return Err(String::from("arena statistics not read"));
+        }
+        let read = part.blocks().collect::<Result<Vec<_>, _>>()?;
+        if read.len() != blocks.len() {
+            return Err(String::from("wrong number of blocks"));
+        }
+        for ((entry, data), (kind, block)) in read.iter().zip(&blocks) {
+            if entry.info.kind != *kind || data != block {
+                return Err(format!("block at {} doesn't match", entry.addr));
+            }
+        }
+        let (_, data) = part.read_clump(&arena, read[1].0.addr)?;
+        if data != blocks[1].1 {
+            return Err(String::from("clump read by address doesn't match"));
+        }
+        if part.read_clump(&arena, read[1].0.addr + 1).is_ok() {
+            return Err(String::from("misaligned clump read not detected"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if damaged partition, arena and trailer headers are errors
+    pub fn arena_bad_headers() -> Result<(), String> {
+        use venti::arena::*;
+        use venti::score::SCORE_SIZE;
+        let image = build_partition(&[(0, b"foofoofoo".to_vec())], true)?;
+        let hdr = PART_BLANK as usize;
+        // a tiny block size would leave no room for the arena headers
+        let mut small = image.clone();
+        small[hdr + 8..hdr + 12].copy_from_slice(&64u32.to_be_bytes());
+        if Partition::open(std::io::Cursor::new(small)).is_ok() {
+            return Err(String::from("64 byte blocks accepted"));
+        }
+        // an arena map reaching past the partition is not read
+        let mut far = image.clone();
+        far[hdr + 12..hdr + 16].copy_from_slice(&u32::MAX.to_be_bytes());
+        if Partition::open(std::io::Cursor::new(far)).is_ok() {
+            return Err(String::from("arena map beyond the partition accepted"));
+        }
+        let mut part = Partition::open(std::io::Cursor::new(image.clone()))?;
+        let arena = part.arena(0)?;
+        let start = arena.start as usize;
+        let stop = arena.stop as usize;
+        for n in [0, 8, ARENA_HEAD_SIZE4 - 1] {
+            if ArenaHead::unpack(&image[start..start + n]).is_ok() {
+                return Err(format!("arena header of {n} bytes accepted"));
+            }
+        }
+        let trailer = &image[stop - 8192..stop];
+        for n in [0, 8, ARENA_SIZE5 - 1, ARENA_SIZE5 + SCORE_SIZE - 1] {
+            if ArenaTrailer::unpack(&trailer[..n]).is_ok() {
+                return Err(format!("sealed arena trailer of {n} bytes accepted"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if arena headers are read as venti lays them out
+    pub fn arena_head_layout() -> Result<(), String> {
+        use venti::arena::*;
+        let mut buf = vec![0u8; ARENA_HEAD_SIZE5];
+        buf[..4].copy_from_slice(&[0xd1, 0x5c, 0x4e, 0xad]);
+        buf[4..8].copy_from_slice(&[0, 0, 0, 5]);
+        buf[8..14].copy_from_slice(b"arena7");
+        buf[72..76].copy_from_slice(&[0, 0, 0x20, 0]);
+        buf[76..84].copy_from_slice(&[0, 0, 0, 0, 0x20, 0, 0, 0]);
+        buf[84..88].copy_from_slice(&[0xd1, 0x5c, 0x4e, 0xad]);
+        let head = ArenaHead::unpack(&buf)?;
+        let want = ArenaHead {
+            version: ARENA_VERSION5,
+            name: String::from("arena7"),
+            clumpmagic: 0xd15c4ead,
+            blocksize: 8192,
+            size: 512 * 1024 * 1024,
+        };
+        if head != want {
+            return Err(format!("version 5 header read as {head:?}"));
+        }
+        let mut packed = vec![0u8; ARENA_HEAD_SIZE5];
+        want.pack(&mut packed);
+        if packed != buf {
+            return Err(String::from("version 5 header not packed as venti's"));
+        }
+        // version 4 has no clump magic
+        buf[4..8].copy_from_slice(&[0, 0, 0, 4]);
+        let head = ArenaHead::unpack(&buf[..ARENA_HEAD_SIZE4])?;
+        if head.blocksize != 8192 || head.size != 512 * 1024 * 1024 {
+            return Err(format!("version 4 header read as {head:?}"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if the arena writer fills up, seals and keeps its statistics
+    pub fn arena_writer() -> Result<(), String> {
+        use venti::arena::*;
+        let mut aw = ArenaWriter::new(
+            std::io::Cursor::new(Vec::new()),
+            0,
+            "small",
+            4 * 8192,
+            8192,
+            0xcafe,
+            100,
+        )?;
+        let mut written = Vec::new();
+        for i in 0..100u32 {
+            let data = text_data(300 + i as usize);
+            match aw.write(0, &data, 100 + i) {
+                Ok(entry) => written.push((entry, data)),
+                Err(_) => break,
+            }
+        }
+        // two blocks of data area, less the directory block
+        if written.is_empty() || aw.stats().used > 8192 {
+            return Err(format!("{} clumps in a 8k data area", written.len()));
+        }
+        let (arena, image) = aw.finish(true)?;
+        let image = image.into_inner();
+        if image.len() != 4 * 8192 || arena.trailer.wtime != 99 + written.len() as u32 {
+            return Err(String::from("arena not laid out as requested"));
+        }
+        let trailer = ArenaTrailer::unpack(&image[3 * 8192..])?;
+        if trailer != arena.trailer || !trailer.diskstats.sealed {
+            return Err(String::from("trailer not written"));
+        }
+        let mut zeroed = image.clone();
+        zeroed[4 * 8192 - 20..].fill(0);
+        if trailer.score != Some(venti::score::Score::of(&zeroed)) {
+            return Err(String::from("arena score is wrong"));
+        }
+        let head = ArenaHead::unpack(&image)?;
+        if head.clumpmagic != 0xcafe || head.size != 4 * 8192 {
+            return Err(String::from("header not written"));
+        }
+        // venti's packarenahead: blocksize and size, then clumpmagic
+        if image[72..76] != 8192u32.to_be_bytes()
+            || image[76..84] != (4 * 8192u64).to_be_bytes()
+            || image[84..88] != 0xcafeu32.to_be_bytes()
+        {
+            return Err(String::from("header not laid out as venti's"));
+        }
+        for (i, (entry, data)) in written.iter().enumerate() {
+            let off = arena.clumpinfo_offset(i as u64) as usize;
+            let info = venti::clump::ClumpInfo::unpack(&image[off..]);
+            let start = (arena.base() + entry.addr) as usize;
+            let (clump, decoded) = venti::clump::decode(&image[start..])?;
+            if info != entry.info || clump.info != info || decoded != *data {
+                return Err(format!("clump {i} not written"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if blocks can be found by score through an index section
+    pub fn index_lookup() -> Result<(), String> {
+        use venti::arena::*;
+        use venti::index::*;
+        let blocks: Vec<(u8, Vec<u8>)> = (0..40)
+            .map(|i| (i % 3, text_data(100 + i as usize)))
+            .collect();
+        let image = build_partition(&blocks, false)?;
+        let mut part = Partition::open(std::io::Cursor::new(image))?;
+        let arena = part.arena(0)?;
+        let entries = part.clumps(&arena)?;
+
+        // an index of four buckets, with arena0 at address 1000
+        let mut is = ISect {
+            version: ISECT_VERSION2,
+            name: String::from("isect0"),
+            index: String::from("main"),
+            blocksize: 8192,
+            blockbase: 0,
+            blocks: 4,
+            start: 0,
+            stop: 4,
+            bucketmagic: 0x5eed,
+        };
+        is.blockbase = (is.tabbase() + 8192) as u32;
+        let mut image = vec![0u8; is.blockbase as usize + 4 * 8192];
+        is.pack(&mut image[PART_BLANK as usize..]);
+        let amap = format_amap(&[AMap {
+            name: String::from("arena0"),
+            start: 1000,
+            stop: 1000 + arena.size(),
+        }]);
+        let tabbase = is.tabbase() as usize;
+        image[tabbase..tabbase + amap.len()].copy_from_slice(&amap);
+        let mut buckets = vec![Vec::new(); 4];
+        for e in &entries {
+            buckets[(e.info.score.0[0] >> 6) as usize].push(IEntry {
+                score: e.info.score,
+                wtime: 0,
+                train: 0,
+                ia: IAddr {
+                    addr: 1000 + e.addr,
+                    size: e.info.uncsize,
+                    kind: e.info.kind,
+                    blocks: IAddr::blocks_for(e.info.size as usize),
+                },
+            });
+        }
+        for (b, bucket) in buckets.iter_mut().enumerate() {
+            bucket.sort_by_key(|e| e.score);
+            let off = is.bucket_offset(b as u32) as usize;
+            pack_bucket(bucket, is.bucketmagic, &mut image[off..off + 8192])?;
+        }
+
+        let mut index = Index::open(vec![std::io::Cursor::new(image.clone())])?;
+        let mut parts = [part];
+        for (kind, data) in &blocks {
+            let score = venti::score::Score::of(data);
+            match read_block(&mut index, &mut parts, &score)? {
+                Some((k, d)) if venti::block::same_type(k, *kind) && d == *data => {}
+                _ => return Err(format!("block {score} not found")),
+            }
+        }
+        if read_block(&mut index, &mut parts, &venti::score::Score::ZERO)?.is_some() {
+            return Err(String::from("found a block that was never stored"));
+        }
+
+        // short headers and buckets are errors
+        for n in [0, 8, ISECT_SIZE1 - 1] {
+            if ISect::unpack(&image[PART_BLANK as usize..][..n]).is_ok() {
+                return Err(format!("index section header of {n} bytes accepted"));
+            }
+        }
+        if ISect::unpack(&image[PART_BLANK as usize..][..ISECT_SIZE2 - 1]).is_ok() {
+            return Err(String::from("short version 2 header accepted"));
+        }
+        if unpack_bucket(&[0; IBUCKET_SIZE - 1], 0).is_ok() {
+            return Err(String::from("short bucket accepted"));
+        }
+
+        // a damaged block base would make a huge arena map
+        let mut huge = is.clone();
+        huge.blockbase = u32::MAX;
+        let mut bad = image.clone();
+        huge.pack(&mut bad[PART_BLANK as usize..]);
+        if Index::open(vec![std::io::Cursor::new(bad)]).is_ok() {
+            return Err(String::from("4GB arena map accepted"));
+        }
+
+        // version 1 sections have no bucket magic and take any bucket
+        is.version = ISECT_VERSION1;
+        is.pack(&mut image[PART_BLANK as usize..]);
+        let mut index = Index::open(vec![std::io::Cursor::new(image)])?;
+        let score = venti::score::Score::of(&blocks[0].1);
+        if read_block(&mut index, &mut parts, &score)?.is_none() {
+            return Err(String::from("block not found through a version 1 section"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if venti messages survive the trip through a buffer
+    pub fn proto_round_trip() -> Result<(), String> {
+        use venti::proto::*;
+        use venti::score::Score;
+        let msgs = vec![
+            Msg::Rerror(String::from("no such block")),
+            Msg::Tping,
+            Msg::Rping,
+            Msg::Thello {
+                version: String::from("02"),
+                uid: String::from("anonymous"),
+                strength: 0,
+                crypto: vec![],
+                codec: vec![1, 2],
+            },
+            Msg::Rhello {
+                sid: String::from("s"),
+                rcrypto: 0,
+                rcodec: 0,
+            },
+            Msg::Tgoodbye,
+            Msg::Tread {
+                score: Score::ZERO,
+                kind: 8,
+                count: 8192,
+            },
+            Msg::Rread(text_data(1000)),
+            Msg::Twrite {
+                kind: 0,
+                data: text_data(500),
+            },
+            Msg::Rwrite(Score::of(b"x")),
+            Msg::Tsync,
+            Msg::Rsync,
+        ];
+        let mut buf = Vec::new();
+        write_version(&mut buf)?;
+        for (tag, msg) in msgs.iter().enumerate() {
+            let f = Fcall {
+                tag: tag as u8,
+                msg: msg.clone(),
+            };
+            write_fcall(&mut buf, &f)?;
+        }
+        let mut r = std::io::Cursor::new(buf);
+        read_version(&mut r)?;
+        for (tag, msg) in msgs.iter().enumerate() {
+            let f = read_fcall(&mut r)?.ok_or("missing message")?;
+            if f.tag != tag as u8 || f.msg != *msg {
+                return Err(format!("message {tag} came back as {f:?}"));
+            }
+        }
+        if read_fcall(&mut r)?.is_some() {
+            return Err(String::from("message after end"));
+        }
+
+        // malformed bodies
+        for bad in [
+            &[VT_TREAD, 0, 1][..],
+            &[VT_TPING, 0, 0],
+            &[99, 0],
+            &[VT_RERROR, 0, 0, 5, b'a'],
+        ] {
+            if Fcall::unpack(bad).is_ok() {
+                return Err(format!("accepted {bad:?}"));
+            }
+        }
+        if read_version(&mut std::io::Cursor::new(b"venti-04-x\n")).is_ok() {
+            return Err(String::from("accepted version 04"));
+        }
+        Ok(())
+    }
+
+    /// fresh path under the temporary directory for test `name`
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("whack-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    /// test if the local venti server stores and returns blocks
+    pub fn venti_server() -> Result<(), String> {
+        use std::io::BufReader;
+        use venti::proto::*;
+        use venti::score::Score;
+        use venti::server::serve;
+        use venti::store::*;
+
+        /// talk to a server on a loopback connection, returning its replies
+        fn session<S: WriteStore + Send + 'static>(
+            mut store: S,
+            msgs: Vec<Msg>,
+        ) -> Result<(Vec<Msg>, S), String> {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+            let addr = listener.local_addr().map_err(|e| e.to_string())?;
+            let server = std::thread::spawn(move || {
+                let (conn, _) = listener.accept().map_err(|e| e.to_string())?;
+                serve(&mut store, conn).map(|_| store)
+            });
+            let conn = std::net::TcpStream::connect(addr).map_err(|e| e.to_string())?;
+            let mut w = conn.try_clone().map_err(|e| e.to_string())?;
+            let mut r = BufReader::new(conn);
+            write_version(&mut w)?;
+            read_version(&mut r)?;
+            let hello = Msg::Thello {
+                version: String::from("02"),
+                uid: String::from("anonymous"),
+                strength: 0,
+                crypto: vec![],
+                codec: vec![],
+            };
+            let mut replies = Vec::new();
+            for (tag, msg) in std::iter::once(hello).chain(msgs).enumerate() {
+                write_fcall(
+                    &mut w,
+                    &Fcall {
+                        tag: tag as u8,
+                        msg,
+                    },
+                )?;
+                let f = read_fcall(&mut r)?.ok_or("server hung up")?;
+                if f.tag != tag as u8 {
+                    return Err(format!("reply tag {} to request {tag}", f.tag));
+                }
+                replies.push(f.msg);
+            }
+            write_fcall(
+                &mut w,
+                &Fcall {
+                    tag: 0,
+                    msg: Msg::Tgoodbye,
+                },
+            )?;
+            let store = server.join().map_err(|_| "server panicked")??;
+            Ok((replies[1..].to_vec(), store))
+        }
+
+        let data = text_data(4000);
+        let score = Score::of(&data);
+        let msgs = vec![
+            Msg::Tping,
+            Msg::Twrite {
+                kind: 3,
+                data: data.clone(),
+            },
+            Msg::Tsync,
+            Msg::Tread {
+                score,
+                kind: 3,
+                count: 8192,
+            },
+            Msg::Tread {
+                score,
+                kind: 2,
+                count: 8192,
+            },
+            Msg::Tread {
+                score: Score::ZERO,
+                kind: 0,
+                count: 0,
+            },
+        ];
+        let dir = temp_path("server-dir");
+        let log = temp_path("server-log");
+        for store in [
+            Box::new(WhackStore::new(DirStore::open(&dir)?)) as Box<dyn WriteStore + Send>,
+            Box::new(WhackStore::new(LogStore::open(&log)?)),
+        ] {
+            let (replies, _) = session(store, msgs.clone())?;
+            match &replies[..] {
+                [
+                    Msg::Rping,
+                    Msg::Rwrite(s),
+                    Msg::Rsync,
+                    Msg::Rread(d),
+                    Msg::Rerror(_),
+                    Msg::Rread(z),
+                ] if *s == score && *d == data && z.is_empty() => {}
+                r => return Err(format!("unexpected replies {r:?}")),
+            }
+        }
+
+        // the log is indexed again when reopened, and stores compressed;
+        // the pointer type came over the wire and is a directory one now
+        let mut store = WhackStore::new(LogStore::open(&log)?);
+        if store.get(&score, venti::block::DIR_TYPE + 3)? != Some(data.clone()) {
+            return Err(String::from("block lost from log"));
+        }
+        let len = std::fs::metadata(&log).map_err(|e| e.to_string())?.len();
+        if len >= data.len() as u64 {
+            return Err(format!("log of {len} bytes not compressed"));
+        }
+
+        // a damaged block is refused
+        let path = DirStore::open(&dir)?.path(&score, venti::block::DIR_TYPE + 3);
+        let mut buf = std::fs::read(&path).map_err(|e| e.to_string())?;
+        let mid = buf.len() / 2;
+        buf[mid] ^= 0x55;
+        std::fs::write(&path, buf).map_err(|e| e.to_string())?;
+        if WhackStore::new(DirStore::open(&dir)?)
+            .get(&score, venti::block::DIR_TYPE + 3)
+            .is_ok()
+        {
+            return Err(String::from("damaged block accepted"));
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+        let _ = std::fs::remove_file(&log);
+        Ok(())
+    }
+
+    #[test]
+    /// test if the client reads and writes through a local server
+    pub fn venti_client() -> Result<(), String> {
+        use std::io::Write;
+        use venti::client::Client;
+        use venti::score::Score;
+        use venti::server::{serve, serve_listener};
+        use venti::store::{LogStore, MemStore, WhackStore};
+        let log = temp_path("client-log");
+        let mut store = WhackStore::new(LogStore::open(&log)?);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        let server = std::thread::spawn(move || {
+            let (conn, _) = listener.accept().map_err(|e| e.to_string())?;
+            serve(&mut store, conn)
+        });
+        let conn = std::net::TcpStream::connect(addr).map_err(|e| e.to_string())?;
+        let mut client = Client::connect(conn, "anonymous")?;
+        client.enable_cache(1 << 20);
+        client.ping()?;
+        let blocks: Vec<Vec<u8>> = (0..8).map(|i| text_data(1000 * (i + 1))).collect();
+        let mut scores = Vec::new();
+        for data in &blocks {
+            scores.push(client.write(0, data)?);
+        }
+        client.sync()?;
+        for (score, data) in scores.iter().zip(&blocks) {
+            if client.read(score, 0, 8192)? != *data {
+                return Err(format!("block {score} read back wrong"));
+            }
+        }
+        let total: usize = blocks.iter().map(|b| b.len()).sum();
+        if client.cache_size() >= total {
+            return Err(format!(
+                "cache of {} bytes not compressed",
+                client.cache_size()
+            ));
+        }
+        // the limit holds for cached blocks too
+        if client.read(&scores[0], 0, blocks[0].len() - 1).is_ok() {
+            return Err(String::from("cached block larger than the limit read"));
+        }
+        // a smaller cache drops the oldest blocks, which are read again
+        client.enable_cache(3000);
+        if client.cache_size() > 3000 {
+            return Err(format!("cache of {} bytes not shrunk", client.cache_size()));
+        }
+        for (score, data) in scores.iter().zip(&blocks) {
+            if client.read(score, 0, 8192)? != *data || client.cache_size() > 3000 {
+                return Err(format!("block {score} read back wrong from a small cache"));
+            }
+        }
+        if !client.read(&Score::ZERO, 0, 0)?.is_empty() {
+            return Err(String::from("zero score is not empty"));
+        }
+        if client.read(&Score::of(b"absent"), 0, 8192).is_ok() {
+            return Err(String::from("read a block never written"));
+        }
+        client.goodbye()?;
+        server.join().map_err(|_| "server panicked")??;
+        let _ = std::fs::remove_file(&log);
+
+        // a listener reports connections that break the protocol
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut store = WhackStore::new(MemStore::new());
+            serve_listener(&mut store, &listener, |e| {
+                let _ = tx.send(e);
+            })
+        });
+        let mut conn = std::net::TcpStream::connect(addr).map_err(|e| e.to_string())?;
+        conn.write_all(b"bogus\n").map_err(|e| e.to_string())?;
+        drop(conn);
+        let report = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .map_err(|_| "broken connection not reported")?;
+        if !report.starts_with("127.0.0.1:") {
+            return Err(format!("report {report} does not name the client"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if blocks lose and regain their trailing zeros by type
+    pub fn zero_truncation() -> Result<(), String> {
+        use venti::block::*;
+        use venti::score::Score;
+        let mut data = text_data(300);
+        data.resize(8192, 0);
+        let short = zero_truncate(DATA_TYPE, &data);
+        if short.len() != 300 {
+            return Err(format!("data block truncated to {}", short.len()));
+        }
+        // whacking the truncated block gives the clump the C venti writes
+        let clump = venti::clump::encode(DATA_TYPE, short, venti::clump::CLUMP_MAGIC, 0, 0)?;
+        let (c, back) = venti::clump::decode(&clump)?;
+        if c.info.score != Score::of(short) || c.info.uncsize != 300 {
+            return Err(format!("clump of truncated block is {c:?}"));
+        }
+        let mut back = back;
+        zero_extend(DATA_TYPE, &mut back, 8192);
+        if back != data {
+            return Err(String::from("data block not restored"));
+        }
+
+        // pointer blocks drop whole zero scores, even ones ending in a zero byte
+        let mut ptrs = Score::of(b"a").0.to_vec();
+        ptrs.extend_from_slice(&[7; 19]);
+        ptrs.push(0);
+        ptrs.extend_from_slice(&Score::ZERO.0);
+        ptrs.extend_from_slice(&Score::ZERO.0);
+        let short = zero_truncate(DIR_TYPE + 1, &ptrs).to_vec();
+        if short.len() != 40 {
+            return Err(format!("pointer block truncated to {}", short.len()));
+        }
+        let mut back = short;
+        zero_extend(DIR_TYPE + 1, &mut back, 85);
+        if back[..80] != ptrs[..] || back[80..] != [0; 5] {
+            return Err(String::from("pointer block not restored"));
+        }
+        // a ragged tail is kept as it is, zero scores before it too
+        let mut ragged = ptrs[..40].to_vec();
+        ragged.extend_from_slice(&Score::ZERO.0);
+        ragged.extend_from_slice(&[0, 3, 0]);
+        if zero_truncate(DIR_TYPE + 1, &ragged) != &ragged[..] {
+            return Err(String::from("ragged pointer block truncated"));
+        }
+        let mut tail = ragged.clone();
+        tail.extend_from_slice(&Score::ZERO.0);
+        let short = zero_truncate(DIR_TYPE + 1, &tail).to_vec();
+        if short != ragged {
+            return Err(format!("ragged pointer block truncated to {}", short.len()));
+        }
+        let mut back = short;
+        zero_extend(DIR_TYPE + 1, &mut back, tail.len() + 7);
+        if back[..tail.len()] != tail[..] || back[tail.len()..] != [0; 7] {
+            return Err(String::from("ragged pointer block not restored"));
+        }
+        let root = vec![0u8; 300];
+        if zero_truncate(ROOT_TYPE, &root).len() != 300 {
+            return Err(String::from("root block truncated"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if entries and roots pack and a tree of blocks reads back
+    pub fn entry_tree() -> Result<(), String> {
+        use venti::block::*;
+        use venti::entry::*;
+        use venti::score::Score;
+        use venti::store::{MemStore, WhackStore, WriteStore};
+        let mut store = WhackStore::new(MemStore::new());
+        // four data blocks, the third all zeros, under pointer blocks of three scores
+        let dsize = 1024;
+        let mut data = text_data(4 * dsize - 100);
+        data[2 * dsize..3 * dsize].fill(0);
+        let mut level = Vec::new();
+        for chunk in data.chunks(dsize) {
+            level.push(store.write(DATA_TYPE, zero_truncate(DATA_TYPE, chunk))?);
+        }
+        let mut kind = DATA_TYPE;
+        while level.len() > 1 {
+            kind += 1;
+            let mut up = Vec::new();
+            for chunk in level.chunks(3) {
+                let p: Vec<u8> = chunk.iter().flat_map(|s| s.0).collect();
+                up.push(store.write(kind, zero_truncate(kind, &p))?);
+            }
+            level = up;
+        }
+        let e = Entry {
+            generation: 7,
+            psize: 60,
+            dsize: dsize as u16,
+            kind,
+            flags: ENTRY_ACTIVE,
+            size: data.len() as u64,
+            score: level[0],
+        };
+        let mut buf = [0u8; ENTRY_SIZE];
+        e.pack(&mut buf);
+        if Entry::unpack(&buf) != e || e.depth() != 2 {
+            return Err(format!("entry came back as {:?}", Entry::unpack(&buf)));
+        }
+        if e.read_all(&mut store)? != data {
+            return Err(String::from("tree read back wrong"));
+        }
+        if e.block(&mut store, 9).is_ok() {
+            return Err(String::from("read a block beyond the tree"));
+        }
+        // a damaged size runs off the end of the tree instead of aborting
+        let huge = Entry {
+            size: (1 << 48) - 1,
+            ..e.clone()
+        };
+        if huge.read_all(&mut store).is_ok() {
+            return Err(String::from("read a stream larger than its tree"));
+        }
+
+        let r = Root {
+            name: String::from("test"),
+            kind: String::from("vac"),
+            score: e.score,
+            blocksize: 8192,
+            prev: Score::ZERO,
+        };
+        let mut buf = [0u8; ROOT_SIZE];
+        r.pack(&mut buf);
+        if Root::unpack(&buf)? != r {
+            return Err(String::from("root came back different"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if a vac archive can be listed and restored
+    pub fn vac_read() -> Result<(), String> {
+        use venti::block::*;
+        use venti::entry::*;
+        use venti::store::{MemStore, WhackStore, WriteStore};
+        use venti::vac::*;
+        let mut store = WhackStore::new(MemStore::new());
+        let entries = |es: &[&Entry]| {
+            let mut p = vec![0u8; es.len() * ENTRY_SIZE];
+            for (i, e) in es.iter().enumerate() {
+                e.pack(&mut p[i * ENTRY_SIZE..]);
+            }
+            p
+        };
+        let meta = |des: &[DirEntry]| -> Result<Vec<u8>, String> {
+            let mut p = vec![0u8; 1024];
+            pack_meta_block(des, &mut p)?;
+            Ok(p)
+        };
+        let dir = |elem: &str, entry, mode| DirEntry {
+            elem: String::from(elem),
+            entry,
+            mentry: entry + 1,
+            uid: String::from("glenda"),
+            mode,
+            ..DirEntry::default()
+        };
+
+        let hello = text_data(3000);
+        let empty = Entry::write(&mut store, DATA_TYPE, &mut &[][..], 60, 1024)?;
+        let sub_source =
+            Entry::write(&mut store, DIR_TYPE, &mut &entries(&[&empty])[..], 60, 1024)?;
+        let sub_meta = Entry::write(
+            &mut store,
+            DATA_TYPE,
+            &mut &meta(&[dir("empty", 0, 0o600)])?[..],
+            60,
+            1024,
+        )?;
+        let hello_source = Entry::write(&mut store, DATA_TYPE, &mut &hello[..], 60, 1024)?;
+        let top_source = Entry::write(
+            &mut store,
+            DIR_TYPE,
+            &mut &entries(&[&hello_source, &sub_source, &sub_meta])[..],
+            60,
+            1024,
+        )?;
+        let top_meta = Entry::write(
+            &mut store,
+            DATA_TYPE,
+            &mut &meta(&[dir("sub", 1, MODE_DIR | 0o755), dir("hello", 0, 0o644)])?[..],
+            60,
+            1024,
+        )?;
+        let root_meta = Entry::write(
+            &mut store,
+            DATA_TYPE,
+            &mut &meta(&[dir("/", 0, MODE_DIR | 0o755)])?[..],
+            60,
+            1024,
+        )?;
+        let top = store.write(DIR_TYPE, &entries(&[&top_source, &top_meta, &root_meta]))?;
+        let mut root = vec![0u8; ROOT_SIZE];
+        Root {
+            name: String::from("test"),
+            kind: String::from(VAC_ROOT_KIND),
+            score: top,
+            blocksize: 1024,
+            prev: venti::score::Score::ZERO,
+        }
+        .pack(&mut root);
+        let score = store.write(ROOT_TYPE, &root)?;
+
+        let mut vac = Vac::open(store, &score)?;
+        let names: Vec<String> = vac
+            .list(&vac.top().clone())?
+            .into_iter()
+            .map(|f| f.dir.elem)
+            .collect();
+        if names != ["hello", "sub"] {
+            return Err(format!("top directory lists {names:?}"));
+        }
+        let mut out = Vec::new();
+        let f = vac.walk("hello")?;
+        vac.read(&f, &mut out)?;
+        if out != hello {
+            return Err(String::from("hello read back wrong"));
+        }
+        if vac.walk("sub/empty")?.source.size != 0 || vac.walk("sub/nothing").is_ok() {
+            return Err(String::from("walk into sub went wrong"));
+        }
+        let mut broken = f.clone();
+        broken.source.dsize = 0;
+        if vac.read_at(&broken, 0, 10).is_ok() {
+            return Err(String::from("read without a block size"));
+        }
+        let path = temp_path("vac-restore");
+        vac.restore(&vac.top().clone(), &path)?;
+        // restoring again replaces the files
+        std::fs::write(path.join("hello"), b"old").map_err(|e| e.to_string())?;
+        vac.restore(&vac.top().clone(), &path)?;
+        let back = std::fs::read(path.join("hello")).map_err(|e| e.to_string())?;
+        let empty = std::fs::read(path.join("sub/empty")).map_err(|e| e.to_string())?;
+        if back != hello || !empty.is_empty() {
+            return Err(String::from("restored files differ"));
+        }
+        // but symbolic links are not followed out of the target
+        #[cfg(unix)]
+        for name in ["hello", "sub"] {
+            let outside = temp_path("vac-restore-outside");
+            std::fs::create_dir_all(&outside).map_err(|e| e.to_string())?;
+            let target = if name == "sub" {
+                outside.clone()
+            } else {
+                outside.join("target")
+            };
+            let link = path.join(name);
+            let _ = std::fs::remove_dir_all(&link);
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(&target, &link).map_err(|e| e.to_string())?;
+            let restored = vac.restore(&vac.top().clone(), &path);
+            let escaped = std::fs::read_dir(&outside)
+                .map_err(|e| e.to_string())?
+                .count();
+            let _ = std::fs::remove_file(&link);
+            let _ = std::fs::remove_dir_all(&outside);
+            if restored.is_ok() || escaped != 0 {
+                return Err(format!("restored through a link at {name}"));
+            }
+        }
+        let _ = std::fs::remove_dir_all(&path);
+
+        // names that would reuse or escape the target are refused
+        let mut store = vac.into_inner();
+        for (bad, entry, mode) in [(".", 1, MODE_DIR | 0o755), ("..", 0, 0o644)] {
+            let bad_meta = Entry::write(
+                &mut store,
+                DATA_TYPE,
+                &mut &meta(&[dir(bad, entry, mode)])?[..],
+                60,
+                1024,
+            )?;
+            let top = store.write(DIR_TYPE, &entries(&[&top_source, &bad_meta, &root_meta]))?;
+            let mut root = vec![0u8; ROOT_SIZE];
+            Root {
+                name: String::from("bad"),
+                kind: String::from(VAC_ROOT_KIND),
+                score: top,
+                blocksize: 1024,
+                prev: venti::score::Score::ZERO,
+            }
+            .pack(&mut root);
+            let score = store.write(ROOT_TYPE, &root)?;
+            let mut vac = Vac::open(store, &score)?;
+            let path = temp_path("vac-restore-bad");
+            let restored = vac.restore(&vac.top().clone(), &path);
+            let _ = std::fs::remove_dir_all(&path);
+            if restored.is_ok() {
+                return Err(format!("restored a file named {bad:?}"));
+            }
+            store = vac.into_inner();
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if a directory tree survives archiving and restoring
+    pub fn vac_write() -> Result<(), String> {
+        use venti::store::{MemStore, WhackStore};
+        use venti::vac::*;
+        let src = temp_path("vac-src");
+        let files: [(&str, Vec<u8>); 4] = [
+            ("a", text_data(20000)),
+            ("empty", Vec::new()),
+            ("d/b", vec![0; 5000]),
+            ("d/e/c", text_data(100)),
+        ];
+        for (name, data) in &files {
+            let p = src.join(name);
+            std::fs::create_dir_all(p.parent().unwrap()).map_err(|e| e.to_string())?;
+            std::fs::write(p, data).map_err(|e| e.to_string())?;
+        }
+        // links are not archived, even ones that lead nowhere
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("nowhere", src.join("dangling")).map_err(|e| e.to_string())?;
+        let mut store = WhackStore::new(MemStore::new());
+        let score = write_vac(&mut store, &src, "test", 1024)?;
+        let mut vac = Vac::open(store, &score)?;
+        let top: Vec<(String, u64)> = vac
+            .list(&vac.top().clone())?
+            .into_iter()
+            .map(|f| (f.dir.elem, f.dir.qid))
+            .collect();
+        // qids go to the top, then in order down the tree
+        let want = [("a", 2), ("d", 3), ("empty", 7)].map(|(e, q)| (String::from(e), q));
+        if top != want {
+            return Err(format!("top directory holds {top:?}"));
+        }
+        let dst = temp_path("vac-dst");
+        vac.restore(&vac.top().clone(), &dst)?;
+        for (name, data) in &files {
+            let back = std::fs::read(dst.join(name)).map_err(|e| e.to_string())?;
+            if back != *data {
+                return Err(format!("{name} restored wrong"));
+            }
+        }
+        // the same tree archives to the same score
+        let mut store = vac.into_inner();
+        let blocks = store.inner().len();
+        if write_vac(&mut store, &src, "test", 1024)? != score || store.inner().len() != blocks {
+            return Err(String::from("archive is not reproducible"));
+        }
+        let _ = std::fs::remove_dir_all(&src);
+        let _ = std::fs::remove_dir_all(&dst);
+        Ok(())
+    }
+
+    #[test]
+    /// test if files are read from a fossil image and its archived blocks
+    pub fn fossil_image() -> Result<(), String> {
+        use venti::block::*;
+        use venti::entry::*;
+        use venti::fossil::*;
+        use venti::score::Score;
+        use venti::store::{BlockStore, MemStore, WhackStore};
+        use venti::vac::*;
+        let src = temp_path("fossil-src");
+        std::fs::create_dir_all(src.join("d")).map_err(|e| e.to_string())?;
+        std::fs::write(src.join("a"), text_data(3000)).map_err(|e| e.to_string())?;
+        std::fs::write(src.join("d/b"), text_data(100)).map_err(|e| e.to_string())?;
+        let mut venti = WhackStore::new(MemStore::new());
+        let last = write_vac(&mut venti, &src, "main", 1024)?;
+        let root = Root::unpack(&venti.get(&last, ROOT_TYPE)?.ok_or("no root")?)?;
+        let top = venti.get(&root.score, DIR_TYPE)?.ok_or("no top block")?;
+
+        // header at block 128, then superblock, label and two data blocks:
+        // the active root and a local copy of the archived top directory
+        let bs = 1024;
+        let h = Header {
+            blocksize: bs as u16,
+            sup: 129,
+            label: 130,
+            data: 131,
+            end: 133,
+        };
+        let mut image = vec![0u8; 133 * bs];
+        h.pack(&mut image[HEADER_OFFSET as usize..]);
+        let sup = Super {
+            epoch_low: 1,
+            epoch_high: 2,
+            qid: 100,
+            active: 0,
+            next: 0,
+            current: 0,
+            last,
+            name: String::from("main"),
+        };
+        sup.pack(&mut image[129 * bs..]);
+        for addr in 0..2 {
+            let label = Label {
+                state: BLOCK_ALLOC,
+                kind: DIR_TYPE,
+                epoch: 2,
+                epoch_close: !0,
+                tag: 7,
+            };
+            label.pack(&mut image[130 * bs + addr * LABEL_SIZE..]);
+        }
+        let e = Entry {
+            generation: 0,
+            psize: root.blocksize / ENTRY_SIZE as u16 * ENTRY_SIZE as u16,
+            dsize: root.blocksize,
+            kind: DIR_TYPE,
+            flags: ENTRY_ACTIVE | ENTRY_LOCAL,
+            size: 3 * ENTRY_SIZE as u64,
+            score: Score::local(1),
+        };
+        e.pack(&mut image[131 * bs..]);
+        // fossil keeps the tag of a local entry ahead of the address
+        image[131 * bs + 32..131 * bs + 36].copy_from_slice(&7u32.to_be_bytes());
+        image[132 * bs..132 * bs + top.len()].copy_from_slice(&top);
+
+        let fossil = Fossil::open(std::io::Cursor::new(image.clone()), venti)?;
+        if fossil.sup != sup || fossil.header != h {
+            return Err(String::from("superblock read wrong"));
+        }
+        let mut fs = fossil.active()?;
+        let names: Vec<String> = fs
+            .list(&fs.top().clone())?
+            .into_iter()
+            .map(|f| f.dir.elem)
+            .collect();
+        if names != ["a", "d"] {
+            return Err(format!("active root lists {names:?}"));
+        }
+        let f = fs.walk("d/b")?;
+        let mut data = Vec::new();
+        fs.read(&f, &mut data)?;
+        if data != text_data(100) {
+            return Err(String::from("active file read wrong"));
+        }
+        let (_, venti) = fs.into_inner().into_inner();
+        let mut fs = Fossil::open(std::io::Cursor::new(image), venti)?.archive()?;
+        let f = fs.walk("a")?;
+        let mut data = Vec::new();
+        fs.read(&f, &mut data)?;
+        if data != text_data(3000) {
+            return Err(String::from("archived file read wrong"));
+        }
+        let _ = std::fs::remove_dir_all(&src);
+        Ok(())
+    }
+
+    #[test]
+    /// test if a trace accounts for every bit and fails where unwhack does
+    pub fn trace_tokens() -> Result<(), String> {
+        use unwhack::*;
+        let src = text_data(5000);
+        let c = whack::whackblock(&src).ok_or("did not compress")?;
+        let trace = unwhack_trace(&c, src.len());
+        if trace.error.is_some() || trace.data != src {
+            return Err(String::from("trace decoded wrong"));
+        }
+        let mut bit = 0;
+        let mut dest = 0;
+        for t in &trace.tokens {
+            if t.bit_position != bit || t.dest_position != dest {
+                return Err(format!("token {t:?} out of place"));
+            }
+            bit += t.bits;
+            dest += match t.token {
+                Token::Literal(_) => 1,
+                Token::Match { len, off_bits, .. } if (off_bits as usize) < t.bits => len,
+                Token::Match { .. } => return Err(format!("token {t:?} without length bits")),
+            };
+        }
+        if bit > c.len() * 8 || dest != src.len() {
+            return Err(format!("tokens cover {bit} bits and {dest} bytes"));
+        }
+        if unwhack_trace(&c, src.len() + 1).error.is_none() {
+            return Err(String::from("short output not flagged"));
+        }
+        if unwhack_trace(&c, usize::MAX).error.is_none() {
+            return Err(String::from("huge output size not flagged"));
+        }
+        let mut bad = c.clone();
+        bad[c.len() / 2] ^= 0x55;
+        let trace = unwhack_trace(&bad, src.len());
+        let message = trace.error.map(|e| e.message);
+        let expected = unwhack::unwhack(&bad, src.len()).err();
+        if expected.is_some() && message != expected {
+            return Err(format!("trace failed with {message:?}, not {expected:?}"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if each way of bailing out of compression is told apart
+    pub fn whack_bail() -> Result<(), String> {
+        use whack::{Bail, try_whack, whackinit};
+        let mut x = 1u32;
+        let mut noise = || {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8 | 0x80
+        };
+        // enough 3 byte matches to pass halfway, then literals that expand
+        let mut expanding = Vec::new();
+        while expanding.len() < 4096 {
+            expanding.extend((0..10).map(|_| noise()));
+            let s = expanding.len().saturating_sub(3000);
+            expanding.extend_from_within(s..s + 3);
+        }
+        expanding.extend((0..4096).map(|_| noise()));
+        let random: Vec<u8> = (0..8192).map(|_| noise()).collect();
+        let cases = [
+            (&b"ab"[..], Bail::TooSmall),
+            (&random[..], Bail::Halfway),
+            (&expanding[..], Bail::Expansion),
+        ];
+        for (src, bail) in cases {
+            let mut stats = new_stats();
+            match try_whack(&mut whackinit(6), src, &mut stats) {
+                Err(b) if b == bail => {}
+                rv => return Err(format!("expected {bail:?}, got {:?}", rv.map(|c| c.len()))),
+            }
+            if whack::whack(&mut whackinit(6), src, &mut stats).is_some() {
+                return Err(format!("whack compressed despite {bail:?}"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if statistics of separate blocks add up to shared statistics
+    pub fn stats_add() -> Result<(), String> {
+        let data = text_data(20000);
+        let mut shared = new_stats();
+        let mut total = new_stats();
+        for block in data.chunks(8192) {
+            let mut own = new_stats();
+            whack::whack(&mut whack::whackinit(6), block, &mut own).ok_or("did not compress")?;
+            whack::whack(&mut whack::whackinit(6), block, &mut shared).ok_or("did not compress")?;
+            total.add(&own);
+        }
+        if format!("{total:?}") != format!("{shared:?}") || total.statbytes != data.len() {
+            return Err(format!("{total:?} differs from {shared:?}"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if framed streams survive compression and catch damage
+    pub fn frame_round_trip() -> Result<(), String> {
+        use frame::*;
+        let mut src = text_data(3 * MAX_BLOCK_SIZE / 2);
+        // a block of noise that does not compress, and a short tail
+        let mut x = 12345u32;
+        src.extend((0..MAX_BLOCK_SIZE).map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        }));
+        src.extend_from_slice(b"ab");
+        let mut stats = new_stats();
+        let mut framed = Vec::new();
+        let (nin, nout) = compress(&mut &src[..], &mut framed, 6, MAX_BLOCK_SIZE, &mut stats)?;
+        if nin != src.len() as u64 || nout != framed.len() as u64 || nout >= nin {
+            return Err(format!("compressed {nin} bytes to {nout}"));
+        }
+        // the first block compresses, venti's ClumpECompress
+        if framed[FRAME_HEADER_SIZE - 1] != 2 {
+            return Err(format!(
+                "first frame encoded {}",
+                framed[FRAME_HEADER_SIZE - 1]
+            ));
+        }
+        let mut back = Vec::new();
+        decompress(&mut &framed[..], &mut back)?;
+        if back != src {
+            return Err(String::from("round trip differs"));
+        }
+        let mut empty = Vec::new();
+        compress(&mut &[][..], &mut empty, 6, 1024, &mut stats)?;
+        if !empty.is_empty() || decompress(&mut &empty[..], &mut back)? != 0 {
+            return Err(String::from("empty input framed"));
+        }
+        if decompress(&mut &framed[..framed.len() - 1], &mut Vec::new()).is_ok() {
+            return Err(String::from("truncated stream accepted"));
+        }
+        framed[4] ^= 0x80;
+        if decompress(&mut &framed[..], &mut Vec::new()).is_ok() {
+            return Err(String::from("damaged frame header accepted"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if a 9P client can browse and read an archive
+    pub fn vacfs_serve() -> Result<(), String> {
+        use std::io::Write;
+        use venti::store::{CacheStore, MemStore, WhackStore};
+        use venti::vac::*;
+        use venti::vacfs::*;
+        let src = temp_path("vacfs-src");
+        std::fs::create_dir_all(src.join("d")).map_err(|e| e.to_string())?;
+        let big = text_data(30000);
+        std::fs::write(src.join("d/big"), &big).map_err(|e| e.to_string())?;
+        std::fs::write(src.join("small"), b"hello").map_err(|e| e.to_string())?;
+        let mut store = WhackStore::new(MemStore::new());
+        let score = write_vac(&mut store, &src, "test", 1024)?;
+        let _ = std::fs::remove_dir_all(&src);
+
+        let mut vac = Vac::open(CacheStore::new(store, 8), &score)?;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        let server = std::thread::spawn(move || {
+            let (conn, _) = listener.accept().map_err(|e| e.to_string())?;
+            serve(&mut vac, conn).map(|_| vac)
+        });
+        let mut conn = std::net::TcpStream::connect(addr).map_err(|e| e.to_string())?;
+        let mut rpc = |t: Tmsg| -> Result<Rmsg, String> {
+            conn.write_all(&t.pack(1)).map_err(|e| e.to_string())?;
+            let buf = read_msg(&mut conn, MAX_MSIZE)?.ok_or("server hung up")?;
+            Ok(Rmsg::unpack(&buf)?.1)
+        };
+
+        let r = rpc(Tmsg::Tversion {
+            msize: 65536,
+            version: String::from("9P2000"),
+        })?;
+        if r != (Rmsg::Rversion {
+            msize: MAX_MSIZE,
+            version: String::from("9P2000"),
+        }) {
+            return Err(format!("version answered {r:?}"));
+        }
+        let attach = Tmsg::Tattach {
+            fid: 0,
+            afid: NOFID,
+            uname: String::from("glenda"),
+            aname: String::new(),
+        };
+        if !matches!(rpc(attach)?, Rmsg::Rattach(q) if q.kind == QTDIR) {
+            return Err(String::from("attach failed"));
+        }
+
+        // list the top directory
+        rpc(Tmsg::Twalk {
+            fid: 0,
+            newfid: 1,
+            wnames: vec![],
+        })?;
+        rpc(Tmsg::Topen {
+            fid: 1,
+            mode: OREAD,
+        })?;
+        let Rmsg::Rread(data) = rpc(Tmsg::Tread {
+            fid: 1,
+            offset: 0,
+            count: 8192,
+        })?
+        else {
+            return Err(String::from("directory read failed"));
+        };
+        let mut names = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let (st, n) = Stat::unpack(rest)?;
+            names.push((st.name, st.length));
+            rest = &rest[n..];
+        }
+        if names != [(String::from("d"), 0), (String::from("small"), 5)] {
+            return Err(format!("top directory lists {names:?}"));
+        }
+
+        // a count too small for the next entry is an error, not the end
+        rpc(Tmsg::Twalk {
+            fid: 0,
+            newfid: 9,
+            wnames: vec![],
+        })?;
+        rpc(Tmsg::Topen {
+            fid: 9,
+            mode: OREAD,
+        })?;
+        let (_, first) = Stat::unpack(&data)?;
+        let small = Tmsg::Tread {
+            fid: 9,
+            offset: 0,
+            count: first as u32 - 1,
+        };
+        if !matches!(rpc(small)?, Rmsg::Rerror(_)) {
+            return Err(String::from("entry cut short by a small count"));
+        }
+        let one = Tmsg::Tread {
+            fid: 9,
+            offset: 0,
+            count: first as u32,
+        };
+        if !matches!(rpc(one)?, Rmsg::Rread(d) if d == data[..first]) {
+            return Err(String::from("first entry not read alone"));
+        }
+
+        // read a file in pieces, after walking through ..
+        let walk = Tmsg::Twalk {
+            fid: 0,
+            newfid: 2,
+            wnames: ["d", "..", "d", "big"].map(String::from).to_vec(),
+        };
+        if !matches!(rpc(walk)?, Rmsg::Rwalk(q) if q.len() == 4) {
+            return Err(String::from("walk failed"));
+        }
+        let Rmsg::Ropen { iounit, .. } = rpc(Tmsg::Topen {
+            fid: 2,
+            mode: OREAD,
+        })?
+        else {
+            return Err(String::from("open failed"));
+        };
+        let mut back = Vec::new();
+        loop {
+            let t = Tmsg::Tread {
+                fid: 2,
+                offset: back.len() as u64,
+                count: iounit,
+            };
+            match rpc(t)? {
+                Rmsg::Rread(d) if d.is_empty() => break,
+                Rmsg::Rread(d) => back.extend_from_slice(&d),
+                r => return Err(format!("read answered {r:?}")),
+            }
+        }
+        if back != big {
+            return Err(String::from("big read back wrong"));
+        }
+
+        // the archive cannot be changed
+        rpc(Tmsg::Twalk {
+            fid: 0,
+            newfid: 3,
+            wnames: vec![String::from("small")],
+        })?;
+        for mode in [1, 2, OREAD | OTRUNC] {
+            if !matches!(rpc(Tmsg::Topen { fid: 3, mode })?, Rmsg::Rerror(_)) {
+                return Err(format!("opened with mode {mode:#x}"));
+            }
+        }
+        // flags that don't write, like OCEXEC, are fine
+        if !matches!(rpc(Tmsg::Topen { fid: 3, mode: 0x20 })?, Rmsg::Ropen { .. }) {
+            return Err(String::from("not opened with OCEXEC"));
+        }
+        let walk = Tmsg::Twalk {
+            fid: 0,
+            newfid: 4,
+            wnames: vec![String::from("nothing")],
+        };
+        if !matches!(rpc(walk)?, Rmsg::Rerror(_)) {
+            return Err(String::from("walked to a missing file"));
+        }
+        let walk = Tmsg::Twalk {
+            fid: 0,
+            newfid: 4,
+            wnames: vec![String::from("d"), String::from("nothing")],
+        };
+        if !matches!(rpc(walk)?, Rmsg::Rwalk(q) if q.len() == 1) {
+            return Err(String::from("partial walk not answered"));
+        }
+        let walk = Tmsg::Twalk {
+            fid: 0,
+            newfid: 4,
+            wnames: vec![String::from(".."); MAXWELEM + 1],
+        };
+        if !matches!(rpc(walk)?, Rmsg::Rerror(_)) {
+            return Err(String::from("walked too many names"));
+        }
+        if rpc(Tmsg::Tclunk { fid: 2 })? != Rmsg::Rclunk {
+            return Err(String::from("clunk failed"));
+        }
+        drop(conn);
+        let vac = server.join().map_err(|_| "server panicked")??;
+        if vac.into_inner().hits == 0 {
+            return Err(String::from("block cache never hit"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// test if the whack store compresses into its inner store and arenas read as stores
+    pub fn block_stores() -> Result<(), String> {
+        use venti::clump::{CLUMP_SIZE, Clump, Encoding};
+        use venti::score::Score;
+        use venti::store::*;
+        let mut store = WhackStore::new(MemStore::new());
+        let data = text_data(8000);
+        let score = store.write(0, &data)?;
+        if store.inner().size() >= data.len() {
+            return Err(String::from("block not compressed"));
+        }
+        let raw = store.into_inner().get(&score, 0)?.ok_or("clump missing")?;
+        let c = Clump::unpack(&raw[..CLUMP_SIZE])?;
+        if c.encoding != Encoding::Compressed || c.info.uncsize as usize != data.len() {
+            return Err(format!("clump header is {c:?}"));
+        }
+        let mut store = WhackStore::new(MemStore::new());
+        store.write(0, &data)?;
+        if store.get(&score, 0)? != Some(data.clone()) || store.get(&score, 1)?.is_some() {
+            return Err(String::from("block read back wrong"));
+        }
+        if store.put(&Score::ZERO, 0, &data).is_ok() {
+            return Err(String::from("stored a block under the wrong score"));
+        }
+
+        // blocks are kept by score and old type in every store
+        let log = temp_path("store-log");
+        let dir = temp_path("store-dir");
+        let stores: [Box<dyn WriteStore>; 3] = [
+            Box::new(MemStore::new()),
+            Box::new(LogStore::open(&log)?),
+            Box::new(DirStore::open(&dir)?),
+        ];
+        for mut store in stores {
+            store.put(&score, 1, b"pointer")?;
+            store.put(&score, 0, b"data")?;
+            store.put(&score, 9, b"again")?;
+            if store.get(&score, 0)?.as_deref() != Some(&b"data"[..])
+                || store.get(&score, 9)?.as_deref() != Some(&b"pointer"[..])
+                || store.get(&score, 8)?.is_some()
+            {
+                return Err(String::from("blocks not kept by score and type"));
+            }
+        }
+        let _ = std::fs::remove_file(&log);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let blocks: Vec<(u8, Vec<u8>)> = (0..5)
+            .map(|i| (i, text_data(500 * (i as usize + 1))))
+            .collect();
+        let image = build_partition(&blocks, true)?;
+        let part = venti::arena::Partition::open(std::io::Cursor::new(image))?;
+        let mut arenas = ArenaStore::open(part)?;
+        for (kind, data) in &blocks {
+            if arenas.get(&Score::of(data), *kind)?.as_ref() != Some(data) {
+                return Err(format!("block of type {kind} missing from arena"));
+            }
+        }
+        Ok(())
+    }
 }
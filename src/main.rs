@@ -0,0 +1,758 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Command-line whack compressor
+//!
+//! `plan9whack compress` and `plan9whack decompress` convert between
+//! files and the framed streams of [`plan9whack::frame`]. With
+//! `--raw-block` they convert a single block to and from a bare
+//! whack stream, as stored in a venti clump. `plan9whack stats`
+//! reports how well files compress in venti-sized blocks and
+//! `plan9whack dump` lists the tokens of a raw block.
+//! `plan9whack arena` lists and extracts the blocks of a venti arena
+//! partition image. `plan9whack bench` times compression of a corpus
+//! at every level that compresses differently.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use plan9whack::frame::{self, MAX_BLOCK_SIZE};
+use plan9whack::unwhack::{Token, unwhack, unwhack_trace};
+use plan9whack::venti::arena::{Arena, Partition};
+use plan9whack::venti::block::same_type;
+use plan9whack::venti::clump::Encoding;
+use plan9whack::venti::score::Score;
+use plan9whack::whack::{
+    Bail, DEFAULT_LEVEL, MAX_LEVEL, MIN_LEVEL, Stats, try_whack, whack, whackinit,
+};
+
+const USAGE: &str = "usage:
+    plan9whack compress [-l level] [-b blocksize] [input [output]]
+    plan9whack compress --raw-block [-l level] [input [output]]
+    plan9whack decompress [input [output]]
+    plan9whack decompress --raw-block -s size [input [output]]
+    plan9whack stats [-l level] [-b blocksize] [--json] file...
+    plan9whack dump -s size [input]
+    plan9whack arena ls image
+    plan9whack arena cat [-t type] image score [output]
+    plan9whack arena extract image dir
+    plan9whack bench [-l level] [-b blocksize] dir...
+
+Input and output default to stdin and stdout, also named by -.";
+
+/// options and operands of a subcommand
+#[derive(Default)]
+struct Args {
+    level: Option<u8>,
+    block_size: Option<usize>,
+    size: Option<usize>,
+    kind: Option<u8>,
+    raw_block: bool,
+    json: bool,
+    files: Vec<String>,
+}
+
+/// block size of `stats`, the usual venti block size
+const STATS_BLOCK_SIZE: usize = 8192;
+
+/// compression of one file by `stats`
+struct FileStats {
+    name: String,
+    blocks: u64,
+    /// blocks that compressed
+    whacked: u64,
+    size: u64,
+    /// bytes of the blocks that compressed
+    whacked_size: u64,
+    /// bytes stored, compressed or not
+    stored: u64,
+    stats: Stats,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((cmd, rest)) = args.split_first() else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let result = match cmd.as_str() {
+        "compress" => Args::parse(rest).and_then(|a| compress(&a)),
+        "decompress" => Args::parse(rest).and_then(|a| decompress(&a)),
+        "stats" => Args::parse(rest).and_then(|a| stats(&a)),
+        "dump" => Args::parse(rest).and_then(|a| dump(&a)),
+        "arena" => Args::parse(rest).and_then(|a| arena(&a)),
+        "bench" => Args::parse(rest).and_then(|a| bench(&a)),
+        "help" | "-h" | "--help" => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        _ => {
+            eprintln!("plan9whack: unknown command {cmd}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("plan9whack: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, String> {
+        let mut a = Args::default();
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            let mut value = |name: &str| -> Result<usize, String> {
+                let v = it.next().ok_or(format!("{name} needs a value"))?;
+                v.parse().map_err(|_| format!("bad value for {name}: {v}"))
+            };
+            match arg.as_str() {
+                "-l" | "--level" => {
+                    let level = value(arg)?;
+                    if !(MIN_LEVEL as usize..=MAX_LEVEL as usize).contains(&level) {
+                        return Err(format!("level {level} outside {MIN_LEVEL} to {MAX_LEVEL}"));
+                    }
+                    a.level = Some(level as u8);
+                }
+                "-b" | "--block-size" => a.block_size = Some(value(arg)?),
+                "-s" | "--size" => {
+                    let size = value(arg)?;
+                    if size > MAX_BLOCK_SIZE {
+                        return Err(format!("size {size} above {MAX_BLOCK_SIZE}"));
+                    }
+                    a.size = Some(size);
+                }
+                "-t" | "--type" => {
+                    let kind = value(arg)?;
+                    a.kind = Some(u8::try_from(kind).map_err(|_| format!("bad type {kind}"))?);
+                }
+                "--raw-block" => a.raw_block = true,
+                "--json" => a.json = true,
+                "-" => a.files.push(arg.clone()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+                _ => a.files.push(arg.clone()),
+            }
+        }
+        Ok(a)
+    }
+
+    /// check for at most an input and an output file
+    fn streams(&self) -> Result<(), String> {
+        if self.json {
+            return Err(String::from("--json only applies to stats"));
+        }
+        if self.files.len() > 2 {
+            return Err(String::from("too many arguments"));
+        }
+        Ok(())
+    }
+
+    /// the input file, or stdin
+    fn input(&self) -> Result<Box<dyn Read>, String> {
+        match self.files.first().map(String::as_str) {
+            None | Some("-") => Ok(Box::new(io::stdin().lock())),
+            Some(path) => File::open(path)
+                .map(|f| Box::new(BufReader::new(f)) as Box<dyn Read>)
+                .map_err(|e| format!("{path}: {e}")),
+        }
+    }
+
+    /// the output file, or stdout
+    fn output(&self) -> Result<Box<dyn Write>, String> {
+        output(self.files.get(1).map(String::as_str))
+    }
+}
+
+/// open `path` for writing, or stdout
+fn output(path: Option<&str>) -> Result<Box<dyn Write>, String> {
+    match path {
+        None | Some("-") => Ok(Box::new(BufWriter::new(io::stdout().lock()))),
+        Some(path) => File::create(path)
+            .map(|f| Box::new(BufWriter::new(f)) as Box<dyn Write>)
+            .map_err(|e| format!("{path}: {e}")),
+    }
+}
+
+fn compress(a: &Args) -> Result<(), String> {
+    if a.size.is_some() || a.kind.is_some() {
+        return Err(String::from("compress takes no size or type"));
+    }
+    let level = a.level.unwrap_or(DEFAULT_LEVEL);
+    a.streams()?;
+    let mut r = a.input()?;
+    let mut w = a.output()?;
+    let mut stats = Stats::default();
+    if a.raw_block {
+        if a.block_size.is_some() {
+            return Err(String::from(
+                "--raw-block takes the whole input as one block",
+            ));
+        }
+        let src = read_block(&mut r)?;
+        let out = whack(&mut whackinit(level), &src, &mut stats)
+            .ok_or("input does not compress, store it plain")?;
+        w.write_all(&out).map_err(|e| format!("write: {e}"))?;
+    } else {
+        let block_size = a.block_size.unwrap_or(MAX_BLOCK_SIZE);
+        frame::compress(&mut r, &mut w, level, block_size, &mut stats)?;
+    }
+    w.flush().map_err(|e| format!("write: {e}"))
+}
+
+fn decompress(a: &Args) -> Result<(), String> {
+    if a.level.is_some() || a.block_size.is_some() || a.kind.is_some() {
+        return Err(String::from(
+            "decompress takes no level, block size or type",
+        ));
+    }
+    a.streams()?;
+    let mut r = a.input()?;
+    let mut w = a.output()?;
+    if a.raw_block {
+        let size = a.size.ok_or("--raw-block needs the size given with -s")?;
+        let src = read_block(&mut r)?;
+        let out = unwhack(&src, size)?;
+        if out.len() != size {
+            return Err(format!(
+                "decompressed to {} bytes, expected {size}",
+                out.len()
+            ));
+        }
+        w.write_all(&out).map_err(|e| format!("write: {e}"))?;
+    } else {
+        if a.size.is_some() {
+            return Err(String::from("-s only applies to --raw-block"));
+        }
+        frame::decompress(&mut r, &mut w)?;
+    }
+    w.flush().map_err(|e| format!("write: {e}"))
+}
+
+fn dump(a: &Args) -> Result<(), String> {
+    if a.files.len() > 1
+        || a.level.is_some()
+        || a.block_size.is_some()
+        || a.kind.is_some()
+        || a.raw_block
+        || a.json
+    {
+        return Err(String::from("dump takes a size and an input file"));
+    }
+    let size = a.size.ok_or("dump needs the size given with -s")?;
+    let src = read_block(&mut a.input()?)?;
+    let trace = unwhack_trace(&src, size);
+    let mut w = BufWriter::new(io::stdout().lock());
+    let mut out = writeln!(w, "{:>8} {:>6} {:>5}  token", "bit", "dest", "bits");
+    let (mut lits, mut matches) = (0, 0);
+    for t in &trace.tokens {
+        let token = match t.token {
+            Token::Literal(b) => {
+                lits += 1;
+                let c = if b.is_ascii_graphic() || b == b' ' {
+                    format!(" '{}'", b as char)
+                } else {
+                    String::new()
+                };
+                format!("literal {b:#04x}{c}")
+            }
+            Token::Match { off, len, off_bits } => {
+                matches += 1;
+                format!(
+                    "match len {len} ({} bits) off {off} ({off_bits} bits)",
+                    t.bits - off_bits as usize
+                )
+            }
+        };
+        out = out.and_then(|_| {
+            writeln!(
+                w,
+                "{:>8} {:>6} {:>5}  {token}",
+                t.bit_position, t.dest_position, t.bits
+            )
+        });
+    }
+    if let Some(e) = &trace.error {
+        out = out.and_then(|_| {
+            writeln!(
+                w,
+                "error at bit {}, output byte {}: {}",
+                e.bit_position,
+                trace.data.len(),
+                e.message
+            )
+        });
+    }
+    out.and_then(|_| {
+        writeln!(
+            w,
+            "{} tokens: {lits} literals, {matches} matches; {} of {size} bytes from {} bits",
+            trace.tokens.len(),
+            trace.data.len(),
+            src.len() * 8
+        )
+    })
+    .and_then(|_| w.flush())
+    .map_err(|e| format!("write: {e}"))?;
+    match trace.error {
+        Some(e) => Err(format!("stream does not decode: {e}")),
+        None => Ok(()),
+    }
+}
+
+fn arena(a: &Args) -> Result<(), String> {
+    if a.level.is_some() || a.block_size.is_some() || a.size.is_some() || a.raw_block || a.json {
+        return Err(String::from("arena takes only a type"));
+    }
+    let (cmd, image, rest) = match a.files.as_slice() {
+        [cmd, image, rest @ ..] => (cmd.as_str(), image, rest),
+        _ => return Err(String::from("arena needs a command and an image")),
+    };
+    if a.kind.is_some() && cmd != "cat" {
+        return Err(format!("arena {cmd} takes no type"));
+    }
+    let f = File::open(image).map_err(|e| format!("{image}: {e}"))?;
+    let mut part = Partition::open(BufReader::new(f)).map_err(|e| format!("{image}: {e}"))?;
+    match (cmd, rest) {
+        ("ls", []) => arena_ls(&mut part),
+        ("cat", [score, out @ ..]) if out.len() <= 1 => {
+            let score: Score = score.parse()?;
+            let data = arena_find(&mut part, &score, a.kind)?;
+            let mut w = output(out.first().map(String::as_str))?;
+            w.write_all(&data)
+                .and_then(|_| w.flush())
+                .map_err(|e| format!("write: {e}"))
+        }
+        ("extract", [dir]) => arena_extract(&mut part, Path::new(dir)),
+        _ => Err(format!("bad arena command {cmd}\n{USAGE}")),
+    }
+}
+
+/// the arenas of a partition, reporting those that cannot be read
+fn arenas<R: Read + Seek>(part: &mut Partition<R>) -> (Vec<Arena>, usize) {
+    let mut arenas = Vec::with_capacity(part.map.len());
+    let mut bad = 0;
+    for n in 0..part.map.len() {
+        match part.arena(n) {
+            Ok(arena) => arenas.push(arena),
+            Err(e) => {
+                eprintln!("plan9whack: arena {}: {e}", part.map[n].name);
+                bad += 1;
+            }
+        }
+    }
+    (arenas, bad)
+}
+
+fn arena_ls<R: Read + Seek>(part: &mut Partition<R>) -> Result<(), String> {
+    let (arenas, mut bad) = arenas(part);
+    let mut w = BufWriter::new(io::stdout().lock());
+    let mut out = writeln!(
+        w,
+        "{:<16} {:>10} {:<40} {:>4} {:>6} {:>7}  encoding",
+        "arena", "addr", "score", "type", "size", "uncsize"
+    );
+    for arena in &arenas {
+        let clumps = match part.clumps(arena) {
+            Ok(clumps) => clumps,
+            Err(e) => {
+                eprintln!("plan9whack: arena {}: {e}", arena.head.name);
+                bad += 1;
+                continue;
+            }
+        };
+        for c in clumps {
+            let encoding = match part.read_clump_header(arena, c.addr) {
+                Ok(h) if h.info != c.info => String::from("header differs from directory"),
+                Ok(h) if h.encoding == Encoding::Compressed => String::from("whack"),
+                Ok(_) => String::from("none"),
+                Err(e) => e,
+            };
+            out = out.and_then(|_| {
+                writeln!(
+                    w,
+                    "{:<16} {:>10} {} {:>4} {:>6} {:>7}  {encoding}",
+                    arena.head.name, c.addr, c.info.score, c.info.kind, c.info.size, c.info.uncsize
+                )
+            });
+        }
+    }
+    out.and_then(|_| w.flush())
+        .map_err(|e| format!("write: {e}"))?;
+    match bad {
+        0 => Ok(()),
+        n => Err(format!("{n} arenas could not be read")),
+    }
+}
+
+/// the first block with `score`, and `kind` if given
+fn arena_find<R: Read + Seek>(
+    part: &mut Partition<R>,
+    score: &Score,
+    kind: Option<u8>,
+) -> Result<Vec<u8>, String> {
+    let (arenas, _) = arenas(part);
+    for arena in &arenas {
+        let Ok(clumps) = part.clumps(arena) else {
+            continue;
+        };
+        let found = clumps
+            .into_iter()
+            .find(|c| c.info.score == *score && kind.is_none_or(|k| same_type(k, c.info.kind)));
+        if let Some(c) = found {
+            let (_, data) = part.read_clump(arena, c.addr)?;
+            if Score::of(&data) != *score {
+                return Err(format!("block {score} does not match its score"));
+            }
+            return Ok(data);
+        }
+    }
+    Err(format!("no block {score}"))
+}
+
+/// write every block as `<score>.<type>` in `dir`, going on past bad ones
+fn arena_extract<R: Read + Seek>(part: &mut Partition<R>, dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let (arenas, mut bad) = arenas(part);
+    let mut blocks = 0;
+    for arena in &arenas {
+        let clumps = match part.clumps(arena) {
+            Ok(clumps) => clumps,
+            Err(e) => {
+                eprintln!("plan9whack: arena {}: {e}", arena.head.name);
+                bad += 1;
+                continue;
+            }
+        };
+        for c in clumps {
+            let data = match part.read_clump(arena, c.addr) {
+                Ok((h, _)) if h.info != c.info => {
+                    Err(String::from("header differs from directory"))
+                }
+                Ok((_, data)) if Score::of(&data) != c.info.score => {
+                    Err(String::from("block does not match its score"))
+                }
+                Ok((_, data)) => Ok(data),
+                Err(e) => Err(e),
+            };
+            let path = dir.join(format!("{}.{}", c.info.score, c.info.kind));
+            match data.and_then(|data| fs::write(&path, data).map_err(|e| e.to_string())) {
+                Ok(()) => blocks += 1,
+                Err(e) => {
+                    eprintln!(
+                        "plan9whack: arena {} clump at {}: {e}",
+                        arena.head.name, c.addr
+                    );
+                    bad += 1;
+                }
+            }
+        }
+    }
+    eprintln!("extracted {blocks} blocks");
+    match bad {
+        0 => Ok(()),
+        n => Err(format!("{n} arenas or blocks could not be extracted")),
+    }
+}
+
+fn bench(a: &Args) -> Result<(), String> {
+    if a.files.is_empty() || a.raw_block || a.size.is_some() || a.kind.is_some() || a.json {
+        return Err(String::from(
+            "bench takes a level, block size and directories",
+        ));
+    }
+    let block_size = a.block_size.unwrap_or(STATS_BLOCK_SIZE);
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(format!("bad block size {block_size}"));
+    }
+    let mut blocks = Vec::new();
+    for path in &a.files {
+        corpus(Path::new(path), block_size, &mut blocks)?;
+    }
+    let size: usize = blocks.iter().map(Vec::len).sum();
+    let levels = match a.level {
+        Some(level) => level..=level,
+        None => MIN_LEVEL..=MAX_LEVEL,
+    };
+    let mut w = BufWriter::new(io::stdout().lock());
+    let mut out = writeln!(
+        w,
+        "{} blocks, {size} bytes in {block_size} byte blocks\n\
+         {:>5} {:>10} {:>10} {:>6} {:>8} {:>9} {:>8} {:>9}",
+        blocks.len(),
+        "level",
+        "comp MB/s",
+        "dec MB/s",
+        "ratio",
+        "whacked",
+        "too small",
+        "halfway",
+        "expansion"
+    );
+    for level in levels {
+        let mut stats = Stats::default();
+        let mut bails: HashMap<Bail, usize> = HashMap::new();
+        let mut compressed = Vec::with_capacity(blocks.len());
+        // bytes stored, compressed or not
+        let mut stored = 0;
+        let start = Instant::now();
+        for (i, block) in blocks.iter().enumerate() {
+            match try_whack(&mut whackinit(level), block, &mut stats) {
+                Ok(c) => {
+                    stored += c.len();
+                    compressed.push((i, c));
+                }
+                Err(bail) => {
+                    stored += block.len();
+                    *bails.entry(bail).or_default() += 1;
+                }
+            }
+        }
+        let comp = start.elapsed();
+        let start = Instant::now();
+        for (i, c) in &compressed {
+            if unwhack(c, blocks[*i].len())? != blocks[*i] {
+                return Err(format!("level {level}: block {i} decompressed wrong"));
+            }
+        }
+        let dec = start.elapsed();
+        let decoded: usize = compressed.iter().map(|(i, _)| blocks[*i].len()).sum();
+        out = out.and_then(|_| {
+            writeln!(
+                w,
+                "{level:>5} {:>10.1} {:>10.1} {:>5.1}% {:>8} {:>9} {:>8} {:>9}",
+                rate(size, comp),
+                rate(decoded, dec),
+                100.0 * stored as f64 / size.max(1) as f64,
+                compressed.len(),
+                bails.get(&Bail::TooSmall).unwrap_or(&0),
+                bails.get(&Bail::Halfway).unwrap_or(&0),
+                bails.get(&Bail::Expansion).unwrap_or(&0)
+            )
+        });
+    }
+    out.and_then(|_| w.flush())
+        .map_err(|e| format!("write: {e}"))
+}
+
+/// megabytes per second
+fn rate(bytes: usize, t: Duration) -> f64 {
+    bytes as f64 / 1e6 / t.as_secs_f64().max(1e-9)
+}
+
+/// cut the files under `path`, in name order, into blocks
+fn corpus(path: &Path, block_size: usize, blocks: &mut Vec<Vec<u8>>) -> Result<(), String> {
+    let meta = fs::symlink_metadata(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    if meta.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .and_then(|d| {
+                d.map(|e| e.map(|e| e.path()))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        entries.sort();
+        for p in entries {
+            corpus(&p, block_size, blocks)?;
+        }
+    } else if meta.is_file() {
+        let data = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        blocks.extend(data.chunks(block_size).map(<[u8]>::to_vec));
+    }
+    Ok(())
+}
+
+/// read a whole raw block, no larger than venti allows
+fn read_block<R: Read>(r: &mut R) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    r.take(MAX_BLOCK_SIZE as u64 + 1)
+        .read_to_end(&mut buf)
+        .map_err(|e| format!("read: {e}"))?;
+    if buf.len() > MAX_BLOCK_SIZE {
+        return Err(format!("raw block larger than {MAX_BLOCK_SIZE} bytes"));
+    }
+    Ok(buf)
+}
+
+fn stats(a: &Args) -> Result<(), String> {
+    if a.files.is_empty() || a.raw_block || a.size.is_some() || a.kind.is_some() {
+        return Err(String::from("stats takes a level, block size and files"));
+    }
+    let level = a.level.unwrap_or(DEFAULT_LEVEL);
+    let block_size = a.block_size.unwrap_or(STATS_BLOCK_SIZE);
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(format!("bad block size {block_size}"));
+    }
+    let start = Instant::now();
+    let mut files = Vec::with_capacity(a.files.len());
+    for name in &a.files {
+        let f = File::open(name).map_err(|e| format!("{name}: {e}"))?;
+        let fs = file_stats(name, BufReader::new(f), level, block_size)
+            .map_err(|e| format!("{name}: {e}"))?;
+        files.push(fs);
+    }
+    let mut total = FileStats::new("total");
+    for fs in &files {
+        total.blocks += fs.blocks;
+        total.whacked += fs.whacked;
+        total.whacked_size += fs.whacked_size;
+        total.size += fs.size;
+        total.stored += fs.stored;
+        total.stats.add(&fs.stats);
+    }
+    let mut w = BufWriter::new(io::stdout().lock());
+    let out = if a.json {
+        let files: Vec<String> = files.iter().map(FileStats::json).collect();
+        writeln!(
+            w,
+            "{{\"level\":{level},\"block_size\":{block_size},\"files\":[{}],\"total\":{}}}",
+            files.join(","),
+            total.json()
+        )
+    } else {
+        writeln!(
+            w,
+            "{:<24} {:>7} {:>7} {:>10} {:>10} {:>6} {:>9} {:>8} {:>10} {:>9} {:>9}",
+            "file",
+            "blocks",
+            "whacked",
+            "in",
+            "out",
+            "ratio",
+            "literals",
+            "matches",
+            "litbits",
+            "offbits",
+            "lenbits"
+        )
+        .and_then(|_| {
+            files
+                .iter()
+                .chain([&total])
+                .try_for_each(|fs| fs.row(&mut w))
+        })
+        .and_then(|_| {
+            writeln!(
+                w,
+                "level {level}, {block_size} byte blocks, {:.2}s",
+                start.elapsed().as_secs_f64()
+            )
+        })
+    };
+    out.and_then(|_| w.flush())
+        .map_err(|e| format!("write: {e}"))
+}
+
+/// compress `r` block by block as venti would
+fn file_stats<R: Read>(
+    name: &str,
+    mut r: R,
+    level: u8,
+    block_size: usize,
+) -> Result<FileStats, String> {
+    let mut fs = FileStats::new(name);
+    let mut block = Vec::with_capacity(block_size);
+    loop {
+        block.clear();
+        r.by_ref()
+            .take(block_size as u64)
+            .read_to_end(&mut block)
+            .map_err(|e| format!("read: {e}"))?;
+        if block.is_empty() {
+            break;
+        }
+        fs.blocks += 1;
+        fs.size += block.len() as u64;
+        match whack(&mut whackinit(level), &block, &mut fs.stats) {
+            Some(c) => {
+                fs.whacked += 1;
+                fs.whacked_size += block.len() as u64;
+                fs.stored += c.len() as u64;
+            }
+            None => fs.stored += block.len() as u64,
+        }
+    }
+    Ok(fs)
+}
+
+impl FileStats {
+    fn new(name: &str) -> FileStats {
+        FileStats {
+            name: name.to_string(),
+            blocks: 0,
+            whacked: 0,
+            size: 0,
+            whacked_size: 0,
+            stored: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    /// stored size as a percentage of the input
+    fn ratio(&self) -> f64 {
+        if self.size == 0 {
+            100.0
+        } else {
+            100.0 * self.stored as f64 / self.size as f64
+        }
+    }
+
+    fn row<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let s = &self.stats;
+        writeln!(
+            w,
+            "{:<24} {:>7} {:>7} {:>10} {:>10} {:>5.1}% {:>9} {:>8} {:>10} {:>9} {:>9}",
+            self.name,
+            self.blocks,
+            self.whacked,
+            self.size,
+            self.stored,
+            self.ratio(),
+            s.statlits,
+            s.statmatches,
+            s.statlitbits,
+            s.statoffbits,
+            s.statlenbits
+        )
+    }
+
+    fn json(&self) -> String {
+        let s = &self.stats;
+        format!(
+            "{{\"file\":{},\"blocks\":{},\"whacked\":{},\"in\":{},\"out\":{},\"ratio\":{:.4},\
+             \"whacked_in\":{},\"whacked_out\":{},\"literals\":{},\"matches\":{},\
+             \"litbits\":{},\"offbits\":{},\"lenbits\":{}}}",
+            json_string(&self.name),
+            self.blocks,
+            self.whacked,
+            self.size,
+            self.stored,
+            self.ratio() / 100.0,
+            self.whacked_size,
+            s.statoutbytes,
+            s.statlits,
+            s.statmatches,
+            s.statlitbits,
+            s.statoffbits,
+            s.statlenbits
+        )
+    }
+}
+
+/// quote `s` as a JSON string
+fn json_string(s: &str) -> String {
+    let mut q = String::with_capacity(s.len() + 2);
+    q.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => q.push_str("\\\""),
+            '\\' => q.push_str("\\\\"),
+            c if (c as u32) < 0x20 => q.push_str(&format!("\\u{:04x}", c as u32)),
+            c => q.push(c),
+        }
+    }
+    q.push('"');
+    q
+}
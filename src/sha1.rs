@@ -0,0 +1,116 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! SHA-1 as used for venti scores
+//!
+//! Small self-contained implementation so that the crate keeps
+//! no runtime dependencies.
+
+/// Incremental SHA-1 state
+#[derive(Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    /// partial input block
+    block: [u8; 64],
+    /// bytes used in `block`
+    nblock: usize,
+    /// total bytes hashed
+    total: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Sha1 {
+        Sha1 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            block: [0; 64],
+            nblock: 0,
+            total: 0,
+        }
+    }
+
+    /// add `data` to the hash
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if self.nblock > 0 {
+            let n = (64 - self.nblock).min(data.len());
+            self.block[self.nblock..self.nblock + n].copy_from_slice(&data[..n]);
+            self.nblock += n;
+            data = &data[n..];
+            if self.nblock < 64 {
+                return;
+            }
+            let block = self.block;
+            self.compress(&block);
+            self.nblock = 0;
+        }
+        while data.len() >= 64 {
+            self.compress(data[..64].try_into().unwrap());
+            data = &data[64..];
+        }
+        self.block[..data.len()].copy_from_slice(data);
+        self.nblock = data.len();
+    }
+
+    /// pad the message and return the digest
+    pub fn finalize(mut self) -> [u8; 20] {
+        let bits = self.total.wrapping_mul(8);
+        let mut pad = [0u8; 72];
+        pad[0] = 0x80;
+        let npad = if self.nblock < 56 {
+            56 - self.nblock
+        } else {
+            120 - self.nblock
+        };
+        pad[npad..npad + 8].copy_from_slice(&bits.to_be_bytes());
+        self.update(&pad[..npad + 8]);
+        let mut digest = [0u8; 20];
+        for (i, word) in self.state.iter().enumerate() {
+            digest[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes(block[4 * i..4 * i + 4].try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+/// hash `data` in one go
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut s = Sha1::new();
+    s.update(data);
+    s.finalize()
+}
@@ -1,143 +1,629 @@
 // Copyright 2024-2026 by Michael Stroucken
 use crate::constants::*;
+use crate::sha1::Sha1;
 
-/// uncompress a section of data
-///
-/// Takes data in `src` and uncompresses to a [`Vec<u8>`]
-/// up to `ndst` bytes. Returns [`String`] for errors.
-///
-/// # Errors
-///
-/// If the output exceeds the specified size or the stream
-/// cannot be correctly interpreted
-pub fn unwhack(src: &[u8], ndst: usize) -> Result<Vec<u8>, String> {
-    let mut dst: Vec<u8> = Vec::with_capacity(ndst);
-    let mut current_dest_pos = 0;
-    let mut current_source_pos = 0;
-    let mut read_bits_count: u32 = 0;
-    let mut read_bits: usize = 0;
-    let mut over_bits_count = 0;
-    let max_source_pos = src.len();
-    let max_dest_pos = ndst;
-    let mut lithist: usize = !0;
-
-    while current_source_pos < max_source_pos || read_bits_count - over_bits_count >= MIN_DECODE {
-        while read_bits_count <= 24 {
-            read_bits <<= 8;
-            if current_source_pos < max_source_pos {
-                read_bits |= src[current_source_pos] as usize;
-                current_source_pos += 1;
+/// bit reader state carried from one token to the next
+struct Bits {
+    read_bits: usize,
+    read_bits_count: u32,
+    over_bits_count: u32,
+    lithist: usize,
+    /// bytes of the stream dropped before the start of the source
+    dropped: usize,
+}
+
+/// A single decoded item of the compressed stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    /// copy of `len` bytes from `off` bytes back
+    Match {
+        off: usize,
+        len: usize,
+        /// bits taken by the offset code, the rest encode the length
+        off_bits: u32,
+    },
+}
+
+impl Bits {
+    fn new() -> Bits {
+        Bits {
+            read_bits: 0,
+            read_bits_count: 0,
+            over_bits_count: 0,
+            lithist: !0,
+            dropped: 0,
+        }
+    }
+
+    /// whether another token can be decoded
+    fn more(&self, src: &[u8], current_source_pos: usize) -> bool {
+        current_source_pos < src.len() || self.read_bits_count >= self.over_bits_count + MIN_DECODE
+    }
+
+    /// number of bits consumed from the source so far
+    fn position(&self, current_source_pos: usize) -> usize {
+        (self.dropped + current_source_pos) * 8 + self.over_bits_count as usize
+            - self.read_bits_count as usize
+    }
+
+    /// whether more bits were consumed than the source provided
+    fn overrun(&self) -> bool {
+        self.read_bits_count < self.over_bits_count
+    }
+
+    /// top up the bit buffer, padding with zero bits past the end of `src`
+    fn fill(&mut self, src: &[u8], current_source_pos: &mut usize) {
+        while self.read_bits_count <= 24 {
+            self.read_bits <<= 8;
+            if *current_source_pos < src.len() {
+                self.read_bits |= src[*current_source_pos] as usize;
+                *current_source_pos += 1;
             } else {
-                over_bits_count += 8;
+                self.over_bits_count += 8;
             }
-            read_bits_count += 8;
+            self.read_bits_count += 8;
         }
+    }
+
+    /// decode the next literal or match
+    fn token(&mut self, src: &[u8], current_source_pos: &mut usize) -> Result<Token, String> {
+        self.fill(src, current_source_pos);
         /*
         literal
          */
-        let mut len = LENVAL[read_bits >> (read_bits_count - 5) & 0x1f] as usize;
+        let mut len = LENVAL[self.read_bits >> (self.read_bits_count - 5) & 0x1f] as usize;
         if len == 0 {
             let mut lit;
-            if lithist & 0xf != 0 {
-                read_bits_count -= 9;
-                lit = (read_bits >> read_bits_count & 0xff) as u8;
+            if self.lithist & 0xf != 0 {
+                self.read_bits_count -= 9;
+                lit = (self.read_bits >> self.read_bits_count & 0xff) as u8;
             } else {
-                read_bits_count -= 8;
-                lit = (read_bits >> read_bits_count & 0x7f) as u8;
+                self.read_bits_count -= 8;
+                lit = (self.read_bits >> self.read_bits_count & 0x7f) as u8;
                 if (lit) < 32 {
                     if (lit) < 24 {
-                        read_bits_count -= 2;
-                        lit = ((lit) << 2) | (read_bits >> read_bits_count & 3) as u8;
+                        self.read_bits_count -= 2;
+                        lit = ((lit) << 2) | (self.read_bits >> self.read_bits_count & 3) as u8;
                     } else {
-                        read_bits_count -= 3;
-                        lit = ((lit) << 3) | (read_bits >> read_bits_count & 7) as u8;
+                        self.read_bits_count -= 3;
+                        lit = ((lit) << 3) | (self.read_bits >> self.read_bits_count & 7) as u8;
                     }
-                    lit -= 64;
+                    // corrupt streams can encode values below 64, wrap like the C code
+                    lit = lit.wrapping_sub(64);
                 }
             }
-            if current_dest_pos >= max_dest_pos {
-                return Err(String::from("too much output"));
-            }
-
-            dst.push(lit);
-            current_dest_pos += 1;
-            lithist = (lithist << 1) | if !(32..=127).contains(&lit) { 1 } else { 0 };
+            self.lithist = (self.lithist << 1) | if !(32..=127).contains(&lit) { 1 } else { 0 };
+            return Ok(Token::Literal(lit));
+        }
+        /*
+        length
+         */
+        if len < 255 {
+            self.read_bits_count -= LENBITS[len] as u32;
         } else {
-            /*
-            length
-             */
-            if len < 255 {
-                read_bits_count -= LENBITS[len] as u32;
-            } else {
-                read_bits_count -= D_BIG_LEN_BITS;
-                let mut code = ((read_bits >> read_bits_count & (((1) << D_BIG_LEN_BITS) - 1))
-                    - D_BIG_LEN_CODE as usize) as u32;
-                len = DMAX_FAST_LEN;
-                let mut use_0 = D_BIG_LEN_BASE;
-                let mut bits = D_BIG_LEN_BITS & 1 ^ 1;
-                while code >= use_0 {
-                    if read_bits_count == 0 {
-                        return Err(String::from("len out of range"));
-                    }
-                    len += use_0 as usize;
-                    code -= use_0;
-                    code <<= 1;
-                    read_bits_count -= 1;
-
-                    code |= (read_bits >> read_bits_count & 1) as u32;
-                    use_0 <<= bits;
-                    bits ^= 1;
-                }
-                len += code as usize;
-                while read_bits_count <= 24 {
-                    read_bits <<= 8;
-                    if current_source_pos < max_source_pos {
-                        read_bits |= src[current_source_pos] as usize;
-                        current_source_pos += 1;
-                    } else {
-                        over_bits_count += 8;
-                    }
-                    read_bits_count += 8;
+            self.read_bits_count -= D_BIG_LEN_BITS;
+            let mut code = ((self.read_bits >> self.read_bits_count
+                & (((1) << D_BIG_LEN_BITS) - 1))
+                - D_BIG_LEN_CODE as usize) as u32;
+            len = DMAX_FAST_LEN;
+            let mut use_0 = D_BIG_LEN_BASE;
+            let mut bits = D_BIG_LEN_BITS & 1 ^ 1;
+            while code >= use_0 {
+                if self.read_bits_count == 0 {
+                    return Err(String::from("len out of range"));
                 }
+                len += use_0 as usize;
+                code -= use_0;
+                code <<= 1;
+                self.read_bits_count -= 1;
+
+                code |= (self.read_bits >> self.read_bits_count & 1) as u32;
+                use_0 <<= bits;
+                bits ^= 1;
+            }
+            len += code as usize;
+            self.fill(src, current_source_pos);
+        }
+        /*
+        offset
+         */
+        self.read_bits_count -= 4;
+        let mut bits = (self.read_bits >> self.read_bits_count & 0xf) as u32;
+        let mut off = OFFBASE[bits as usize] as usize;
+        bits = OFFBITS[bits as usize] as u32;
+        self.read_bits_count -= bits;
+        off |= (self.read_bits >> self.read_bits_count) & (((1) << bits) - 1);
+        off += 1;
+        Ok(Token::Match {
+            off,
+            len,
+            off_bits: 4 + bits,
+        })
+    }
+}
+
+/// why and where decoding of a stream stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    /// description of the failure, as returned by [`unwhack`]
+    pub message: String,
+    /// offset in bits into the source of the failing token
+    pub bit_position: usize,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at bit {}", self.message, self.bit_position)
+    }
+}
+
+/// Outcome of [`unwhack_salvage`]
+pub struct Salvaged {
+    /// bytes recovered from the stream
+    pub data: Vec<u8>,
+    /// the error that stopped decoding, if any
+    pub error: Option<DecodeError>,
+    /// matches that could not be resolved and were replaced by zero bytes
+    pub zeroed: Vec<DecodeError>,
+}
+
+/// how the token loop treats the end of the output and bad matches
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// fail on any error
+    Whole,
+    /// stop quietly once the output is full
+    Prefix,
+    /// replace matches with out of range offsets by zero bytes
+    ZeroFill,
+}
+
+/// Token decoded by [`unwhack_trace`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedToken {
+    /// offset in bits into the source where the token starts
+    pub bit_position: usize,
+    /// number of bits the token takes
+    pub bits: usize,
+    /// offset in the output of the bytes the token produces
+    pub dest_position: usize,
+    pub token: Token,
+}
+
+/// Outcome of [`unwhack_trace`]
+pub struct Trace {
+    /// every token decoded before the stream ended or failed
+    pub tokens: Vec<TracedToken>,
+    /// bytes decoded
+    pub data: Vec<u8>,
+    /// the error that stopped decoding, or the shortfall of the output
+    pub error: Option<DecodeError>,
+}
+
+/// Outcome of [`unwhack_verify`]
+pub struct Verified {
+    /// number of bytes the stream decodes to
+    pub len: usize,
+    /// SHA-1 digest of the decoded bytes
+    pub sha1: [u8; 20],
+}
+
+/// destination of decoded bytes
+trait Output {
+    /// bytes produced so far
+    fn len(&self) -> usize;
+    fn push(&mut self, b: u8);
+    /// append `len` bytes copied from `off` bytes back
+    fn copy(&mut self, off: usize, len: usize);
+    fn zeros(&mut self, len: usize) {
+        for _ in 0..len {
+            self.push(0);
+        }
+    }
+}
+
+impl Output for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn push(&mut self, b: u8) {
+        Vec::push(self, b);
+    }
+
+    fn copy(&mut self, off: usize, len: usize) {
+        let s = Vec::len(self) - off;
+
+        // can't use extend_from_within, because the vector grows with data that will be used
+        //dst.extend_from_within(s..s + len);
+        let mut i = 0;
+        while i < len {
+            Vec::push(self, self[s + i]);
+            i += 1;
+        }
+    }
+
+    fn zeros(&mut self, len: usize) {
+        self.resize(Vec::len(self) + len, 0);
+    }
+}
+
+/// hashes the output, keeping only as much history as a match can reach
+struct Window {
+    buf: Vec<u8>,
+    /// bytes produced so far
+    len: usize,
+    /// bytes already fed to `hash`
+    hashed: usize,
+    hash: Sha1,
+}
+
+impl Output for Window {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, b: u8) {
+        if self.len - self.hashed == self.buf.len() {
+            self.hash.update(&self.buf);
+            self.hashed = self.len;
+        }
+        let n = self.buf.len();
+        self.buf[self.len % n] = b;
+        self.len += 1;
+    }
+
+    fn copy(&mut self, off: usize, len: usize) {
+        let n = self.buf.len();
+        for _ in 0..len {
+            self.push(self.buf[(self.len - off) % n]);
+        }
+    }
+}
+
+/// decode one token from `src` into `dst`
+///
+/// In [`Mode::ZeroFill`] a substituted match is recorded in `zeroed`.
+fn step<O: Output>(
+    bits: &mut Bits,
+    src: &[u8],
+    current_source_pos: &mut usize,
+    ndst: usize,
+    dst: &mut O,
+    mode: Mode,
+    zeroed: &mut Vec<DecodeError>,
+) -> Result<Token, DecodeError> {
+    let bit_position = bits.position(*current_source_pos);
+    let fail = |message| DecodeError {
+        message,
+        bit_position,
+    };
+    let token = bits.token(src, current_source_pos).map_err(fail)?;
+    match token {
+        Token::Literal(lit) => {
+            if dst.len() >= ndst {
+                return Err(fail(String::from("too much output")));
             }
-            /*
-            offset
-             */
-            read_bits_count -= 4;
-            let mut bits = (read_bits >> read_bits_count & 0xf) as u32;
-            let mut off = OFFBASE[bits as usize] as usize;
-            bits = OFFBITS[bits as usize] as u32;
-            read_bits_count -= bits;
-            off |= (read_bits >> read_bits_count) & (((1) << bits) - 1);
-            off += 1;
+            dst.push(lit);
+        }
+        Token::Match { off, mut len, .. } => {
+            let current_dest_pos = dst.len();
+            let mut resolvable = true;
             if off > current_dest_pos {
-                return Err(format!(
-                    "offset out of range: off={off} d={current_dest_pos} len={len} nbits={read_bits_count}",
+                let error = fail(format!(
+                    "offset out of range: off={off} d={current_dest_pos} len={len} nbits={}",
+                    bits.read_bits_count
                 ));
+                if mode != Mode::ZeroFill {
+                    return Err(error);
+                }
+                zeroed.push(error);
+                resolvable = false;
             }
 
-            if current_dest_pos + len > max_dest_pos {
-                return Err(String::from("len out of range"));
+            if current_dest_pos + len > ndst {
+                if mode != Mode::Prefix {
+                    return Err(fail(String::from("len out of range")));
+                }
+                len = ndst - current_dest_pos;
             }
 
-            let s = current_dest_pos - off;
-
-            // can't use extend_from_within, because the vector grows with data that will be used
-            //dst.extend_from_within(s..s + len);
-            let mut i = 0;
-            while i < len {
-                dst.push(dst[s + i]);
-                i += 1;
+            if resolvable {
+                dst.copy(off, len);
+            } else {
+                dst.zeros(len);
             }
-
-            current_dest_pos += len;
         }
     }
-    if read_bits_count < over_bits_count {
-        return Err(String::from("compressed data overrun"));
+    Ok(token)
+}
+
+/// check that the stream did not end in the middle of a token
+fn overrun(bits: &Bits, current_source_pos: usize) -> Result<(), DecodeError> {
+    if bits.overrun() {
+        return Err(DecodeError {
+            message: String::from("compressed data overrun"),
+            bit_position: bits.position(current_source_pos),
+        });
+    }
+    Ok(())
+}
+
+/// run the token loop over `src`, appending to `dst`
+///
+/// In [`Mode::ZeroFill`] every substituted match is recorded in `zeroed`.
+fn decode<O: Output>(
+    src: &[u8],
+    ndst: usize,
+    dst: &mut O,
+    mode: Mode,
+    zeroed: &mut Vec<DecodeError>,
+) -> Result<(), DecodeError> {
+    let mut bits = Bits::new();
+    let mut current_source_pos = 0;
+
+    while bits.more(src, current_source_pos) {
+        if mode == Mode::Prefix && dst.len() >= ndst {
+            return Ok(());
+        }
+        step(
+            &mut bits,
+            src,
+            &mut current_source_pos,
+            ndst,
+            dst,
+            mode,
+            zeroed,
+        )?;
     }
+    overrun(&bits, current_source_pos)?;
 
     //len = dpos;
     //assert_eq!(len, ndst);
+    Ok(())
+}
+
+/// uncompress a section of data
+///
+/// Takes data in `src` and uncompresses to a [`Vec<u8>`]
+/// up to `ndst` bytes. Returns [`String`] for errors.
+///
+/// # Errors
+///
+/// If the output exceeds the specified size or the stream
+/// cannot be correctly interpreted
+pub fn unwhack(src: &[u8], ndst: usize) -> Result<Vec<u8>, String> {
+    let mut dst: Vec<u8> = Vec::with_capacity(ndst);
+    decode(src, ndst, &mut dst, Mode::Whole, &mut Vec::new()).map_err(|e| e.message)?;
+    Ok(dst)
+}
+
+/// uncompress the beginning of a section of data
+///
+/// Takes data in `src` and uncompresses only until `n` bytes
+/// have been produced, ignoring the rest of the stream. Fewer
+/// bytes are returned if the stream ends early.
+///
+/// # Errors
+///
+/// If the stream cannot be correctly interpreted up to that point
+pub fn unwhack_prefix(src: &[u8], n: usize) -> Result<Vec<u8>, String> {
+    let mut dst: Vec<u8> = Vec::with_capacity(n);
+    decode(src, n, &mut dst, Mode::Prefix, &mut Vec::new()).map_err(|e| e.message)?;
     Ok(dst)
 }
+
+/// uncompress as much of a damaged section of data as possible
+///
+/// Takes data in `src` and uncompresses up to `ndst` bytes like
+/// [`unwhack`], but instead of discarding the output on failure it
+/// returns everything decoded before the error, along with the error
+/// and its bit position. With `zero_fill`, matches that reach back
+/// before the start of the output are replaced by zero bytes and
+/// decoding carries on, so that later data can still be recovered.
+pub fn unwhack_salvage(src: &[u8], ndst: usize, zero_fill: bool) -> Salvaged {
+    let mut dst: Vec<u8> = Vec::with_capacity(ndst);
+    let mut zeroed = Vec::new();
+    let mode = if zero_fill {
+        Mode::ZeroFill
+    } else {
+        Mode::Whole
+    };
+    let error = decode(src, ndst, &mut dst, mode, &mut zeroed).err();
+    Salvaged {
+        data: dst,
+        error,
+        zeroed,
+    }
+}
+
+/// check a section of data without keeping the output
+///
+/// Walks the stream in `src` exactly like [`unwhack`] with a limit
+/// of `ndst` bytes, but only holds the last 16 KiB (`WHACK_MAX_OFF`)
+/// of output, which is as far back as a match can reach. Returns the
+/// decoded length and its SHA-1 digest, as used for venti scores.
+///
+/// # Errors
+///
+/// If the output exceeds the specified size or the stream
+/// cannot be correctly interpreted
+pub fn unwhack_verify(src: &[u8], ndst: usize) -> Result<Verified, String> {
+    let mut window = Window {
+        buf: vec![0; WHACK_MAX_OFF as usize],
+        len: 0,
+        hashed: 0,
+        hash: Sha1::new(),
+    };
+    decode(src, ndst, &mut window, Mode::Whole, &mut Vec::new()).map_err(|e| e.message)?;
+    let unhashed = window.len - window.hashed;
+    window.hash.update(&window.buf[..unhashed]);
+    Ok(Verified {
+        len: window.len,
+        sha1: window.hash.finalize(),
+    })
+}
+
+/// trace the decoding of a section of data
+///
+/// Decodes `src` exactly like [`unwhack`] with a limit of `ndst`
+/// bytes and records each token with its position and width in
+/// bits. Decoding stops at the first error, which is returned with
+/// the tokens before it. A stream that decodes to fewer than `ndst`
+/// bytes is reported as an error at its end.
+pub fn unwhack_trace(src: &[u8], ndst: usize) -> Trace {
+    let mut bits = Bits::new();
+    let mut current_source_pos = 0;
+    let mut trace = Trace {
+        tokens: Vec::new(),
+        // `ndst` may come from a damaged header, let the output grow
+        data: Vec::new(),
+        error: None,
+    };
+    while bits.more(src, current_source_pos) {
+        let bit_position = bits.position(current_source_pos);
+        let dest_position = trace.data.len();
+        match step(
+            &mut bits,
+            src,
+            &mut current_source_pos,
+            ndst,
+            &mut trace.data,
+            Mode::Whole,
+            &mut Vec::new(),
+        ) {
+            Ok(token) => trace.tokens.push(TracedToken {
+                bit_position,
+                bits: bits.position(current_source_pos) - bit_position,
+                dest_position,
+                token,
+            }),
+            Err(e) => {
+                trace.error = Some(e);
+                return trace;
+            }
+        }
+    }
+    trace.error = overrun(&bits, current_source_pos).err();
+    if trace.error.is_none() && trace.data.len() != ndst {
+        trace.error = Some(DecodeError {
+            message: format!("short output: {} of {ndst} bytes", trace.data.len()),
+            bit_position: bits.position(current_source_pos),
+        });
+    }
+    trace
+}
+
+/// most input a single token can pull into the bit buffer
+const MAX_TOKEN_BYTES: usize = 8;
+
+/// Progress report from [`Decoder::feed`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// bytes of output decoded so far
+    pub produced: usize,
+    /// bytes of input held back until more arrives
+    pub pending: usize,
+}
+
+/// Incremental decompressor
+///
+/// Takes a compressed stream in pieces of any size, as they arrive
+/// from the network, and produces the same output as [`unwhack`]
+/// on the whole stream. A token is only decoded once all bytes it
+/// could need are present, so a piece may end anywhere, even in
+/// the middle of a length escape or offset field; the partial field
+/// is kept and decoding resumes with the next call to
+/// [`Decoder::feed`].
+pub struct Decoder {
+    ndst: usize,
+    /// input received and not yet dropped
+    input: Vec<u8>,
+    /// next byte of `input` for the bit buffer; the bytes before it
+    /// are dropped at the end of each [`Decoder::feed`]
+    current_source_pos: usize,
+    bits: Bits,
+    dst: Vec<u8>,
+    /// first error, repeated on later calls
+    failed: Option<String>,
+}
+
+impl Decoder {
+    /// Create a decoder for a block of at most `ndst` bytes
+    pub fn new(ndst: usize) -> Decoder {
+        Decoder {
+            ndst,
+            input: Vec::new(),
+            current_source_pos: 0,
+            bits: Bits::new(),
+            dst: Vec::with_capacity(ndst),
+            failed: None,
+        }
+    }
+
+    /// Decode as much of the stream as `data` completes
+    ///
+    /// # Errors
+    ///
+    /// If the output exceeds the specified size or the stream
+    /// cannot be correctly interpreted
+    pub fn feed(&mut self, data: &[u8]) -> Result<Progress, String> {
+        if let Some(e) = &self.failed {
+            return Err(e.clone());
+        }
+        self.input.extend_from_slice(data);
+        while self.input.len() - self.current_source_pos >= MAX_TOKEN_BYTES {
+            if let Err(e) = step(
+                &mut self.bits,
+                &self.input,
+                &mut self.current_source_pos,
+                self.ndst,
+                &mut self.dst,
+                Mode::Whole,
+                &mut Vec::new(),
+            ) {
+                self.failed = Some(e.message.clone());
+                return Err(e.message);
+            }
+        }
+        self.input.drain(..self.current_source_pos);
+        self.bits.dropped += self.current_source_pos;
+        self.current_source_pos = 0;
+        Ok(Progress {
+            produced: self.dst.len(),
+            pending: self.input.len() - self.current_source_pos,
+        })
+    }
+
+    /// Output decoded so far
+    pub fn output(&self) -> &[u8] {
+        &self.dst
+    }
+
+    /// Decode the rest of the stream and return the output
+    ///
+    /// # Errors
+    ///
+    /// If the output exceeds the specified size or the stream
+    /// cannot be correctly interpreted
+    pub fn finish(mut self) -> Result<Vec<u8>, String> {
+        if let Some(e) = self.failed {
+            return Err(e);
+        }
+        while self.bits.more(&self.input, self.current_source_pos) {
+            step(
+                &mut self.bits,
+                &self.input,
+                &mut self.current_source_pos,
+                self.ndst,
+                &mut self.dst,
+                Mode::Whole,
+                &mut Vec::new(),
+            )
+            .map_err(|e| e.message)?;
+        }
+        overrun(&self.bits, self.current_source_pos).map_err(|e| e.message)?;
+        Ok(self.dst)
+    }
+}
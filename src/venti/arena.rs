@@ -0,0 +1,745 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti arena partitions
+//!
+//! An arena partition starts with an unused area of [`PART_BLANK`]
+//! bytes, followed by the partition header, a text map naming the
+//! arenas and their extent, and the arenas themselves. Each arena
+//! has a header block, clumps growing upwards from the start of its
+//! data area, a clump directory growing downwards from its end, and
+//! a trailer block holding the statistics.
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::clump::{self, CLUMP_INFO_SIZE, CLUMP_MAGIC, CLUMP_SIZE, Clump, ClumpInfo, Encoding};
+use super::score::{SCORE_SIZE, Score};
+use super::{nameget, nameput, read_at, u32get, u32put, u64get, u64put};
+use crate::sha1::Sha1;
+
+pub const ARENA_PART_MAGIC: u32 = 0xa9e4a5e7;
+/// arena trailer
+pub const ARENA_MAGIC: u32 = 0xf2a14ead;
+/// arena header
+pub const ARENA_HEAD_MAGIC: u32 = 0xd15c4ead;
+pub const ARENA_PART_VERSION: u32 = 3;
+pub const ARENA_VERSION4: u32 = 4;
+/// adds a clump magic chosen per arena
+pub const ARENA_VERSION5: u32 = 5;
+/// untouched section at the beginning of a partition
+pub const PART_BLANK: u64 = 256 * 1024;
+/// size of a header after [`PART_BLANK`]
+pub const HEAD_SIZE: u64 = 512;
+/// size of an arena name field
+pub const ANAME_SIZE: usize = 64;
+pub const ARENA_PART_SIZE: usize = 4 * 4;
+pub const ARENA_HEAD_SIZE4: usize = 8 + 3 * 4 + ANAME_SIZE;
+pub const ARENA_HEAD_SIZE5: usize = ARENA_HEAD_SIZE4 + 4;
+pub const ARENA_SIZE4: usize = 2 * 8 + 6 * 4 + ANAME_SIZE + 1;
+pub const ARENA_SIZE5: usize = ARENA_SIZE4 + 4;
+/// with the in-memory statistics appended
+pub const ARENA_SIZE5A: usize = ARENA_SIZE5 + 2 + 2 * 4 + 2 * 8;
+/// smallest block that holds a trailer and its score
+pub const MIN_BLOCK_SIZE: usize = ARENA_SIZE5A + SCORE_SIZE;
+/// most of the arena map that is read; the map is text ending in a NUL
+pub const MAX_AMAP_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Arena partition header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaPart {
+    pub version: u32,
+    pub blocksize: u32,
+    /// offset of the first arena
+    pub arenabase: u32,
+}
+
+/// Entry in an arena map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AMap {
+    pub name: String,
+    pub start: u64,
+    pub stop: u64,
+}
+
+/// Arena header block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaHead {
+    pub version: u32,
+    pub name: String,
+    pub clumpmagic: u32,
+    pub blocksize: u32,
+    /// size of the whole arena, header and trailer included
+    pub size: u64,
+}
+
+/// Arena usage statistics
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// number of clumps
+    pub clumps: u32,
+    /// number of compressed clumps
+    pub cclumps: u32,
+    /// bytes of the data area in use
+    pub used: u64,
+    /// sum of the uncompressed clump sizes
+    pub uncsize: u64,
+    /// no more clumps can be added
+    pub sealed: bool,
+}
+
+/// Arena trailer block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaTrailer {
+    pub version: u32,
+    pub name: String,
+    /// creation time
+    pub ctime: u32,
+    /// time of the last write
+    pub wtime: u32,
+    pub clumpmagic: u32,
+    /// statistics as last synced with the index
+    pub diskstats: ArenaStats,
+    /// more recent statistics, if the arena kept them
+    pub memstats: Option<ArenaStats>,
+    /// score of the whole arena, once sealed
+    pub score: Option<Score>,
+}
+
+impl ArenaPart {
+    /// Pack into the first [`ARENA_PART_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        u32put(buf, ARENA_PART_MAGIC);
+        u32put(&mut buf[4..], self.version);
+        u32put(&mut buf[8..], self.blocksize);
+        u32put(&mut buf[12..], self.arenabase);
+    }
+
+    /// Unpack from the first [`ARENA_PART_SIZE`] bytes of `buf`
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is short, the magic or version is wrong or the block
+    /// size is unusable
+    pub fn unpack(buf: &[u8]) -> Result<ArenaPart, String> {
+        if buf.len() < ARENA_PART_SIZE {
+            return Err(format!("arena partition header of {} bytes", buf.len()));
+        }
+        let magic = u32get(buf);
+        if magic != ARENA_PART_MAGIC {
+            return Err(format!("bad arena partition magic {magic:#x}"));
+        }
+        let ap = ArenaPart {
+            version: u32get(&buf[4..]),
+            blocksize: u32get(&buf[8..]),
+            arenabase: u32get(&buf[12..]),
+        };
+        if ap.version != ARENA_PART_VERSION {
+            return Err(format!("unknown arena partition version {}", ap.version));
+        }
+        if !ap.blocksize.is_power_of_two() || (ap.blocksize as usize) < MIN_BLOCK_SIZE {
+            return Err(format!("bad arena partition block size {}", ap.blocksize));
+        }
+        Ok(ap)
+    }
+
+    /// Offset of the arena map
+    pub fn tabbase(&self) -> u64 {
+        let blocksize = self.blocksize as u64;
+        (PART_BLANK + HEAD_SIZE + blocksize - 1) & !(blocksize - 1)
+    }
+
+    /// Space reserved for the arena map
+    pub fn tabsize(&self) -> u64 {
+        (self.arenabase as u64).saturating_sub(self.tabbase())
+    }
+}
+
+/// Parse an arena map
+///
+/// The map is text: the number of entries, then for each entry
+/// its name, start and stop offset, all separated by white space.
+/// It ends at the first NUL byte.
+///
+/// # Errors
+///
+/// If the map is malformed
+pub fn parse_amap(buf: &[u8]) -> Result<Vec<AMap>, String> {
+    let n = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    let text = std::str::from_utf8(&buf[..n]).map_err(|_| "arena map is not text")?;
+    let mut words = text.split_ascii_whitespace();
+    let count: usize = words
+        .next()
+        .and_then(|w| w.parse().ok())
+        .ok_or("bad arena map count")?;
+    let mut map = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let (Some(name), Some(start), Some(stop)) = (words.next(), words.next(), words.next())
+        else {
+            return Err(String::from("short arena map"));
+        };
+        let start = start.parse().map_err(|_| format!("bad start for {name}"))?;
+        let stop = stop.parse().map_err(|_| format!("bad stop for {name}"))?;
+        if start > stop {
+            return Err(format!("arena {name} ends before it starts"));
+        }
+        map.push(AMap {
+            name: name.to_string(),
+            start,
+            stop,
+        });
+    }
+    Ok(map)
+}
+
+/// Format an arena map as [`parse_amap`] reads it
+pub fn format_amap(map: &[AMap]) -> Vec<u8> {
+    let mut text = format!("{}\n", map.len());
+    for m in map {
+        text += &format!("{}\t{}\t{}\n", m.name, m.start, m.stop);
+    }
+    text.into_bytes()
+}
+
+impl ArenaHead {
+    /// Pack into the start of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        u32put(buf, ARENA_HEAD_MAGIC);
+        u32put(&mut buf[4..], self.version);
+        nameput(&mut buf[8..8 + ANAME_SIZE], &self.name);
+        let p = 8 + ANAME_SIZE;
+        u32put(&mut buf[p..], self.blocksize);
+        u64put(&mut buf[p + 4..], self.size);
+        if self.version == ARENA_VERSION5 {
+            u32put(&mut buf[p + 12..], self.clumpmagic);
+        }
+    }
+
+    /// Unpack from the start of `buf`
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is short or the magic or version is wrong
+    pub fn unpack(buf: &[u8]) -> Result<ArenaHead, String> {
+        if buf.len() < ARENA_HEAD_SIZE4 {
+            return Err(format!("arena header of {} bytes", buf.len()));
+        }
+        let magic = u32get(buf);
+        if magic != ARENA_HEAD_MAGIC {
+            return Err(format!("bad arena header magic {magic:#x}"));
+        }
+        let version = u32get(&buf[4..]);
+        if version == ARENA_VERSION5 && buf.len() < ARENA_HEAD_SIZE5 {
+            return Err(format!("arena header of {} bytes", buf.len()));
+        }
+        let name = nameget(&buf[8..8 + ANAME_SIZE]);
+        let p = 8 + ANAME_SIZE;
+        let clumpmagic = match version {
+            ARENA_VERSION4 => CLUMP_MAGIC,
+            ARENA_VERSION5 => u32get(&buf[p + 12..]),
+            _ => return Err(format!("unknown arena version {version}")),
+        };
+        Ok(ArenaHead {
+            version,
+            name,
+            clumpmagic,
+            blocksize: u32get(&buf[p..]),
+            size: u64get(&buf[p + 4..]),
+        })
+    }
+}
+
+impl ArenaTrailer {
+    /// Pack into the start of a trailer block
+    ///
+    /// The score, if any, goes into the last [`SCORE_SIZE`] bytes of `buf`.
+    pub fn pack(&self, buf: &mut [u8]) {
+        u32put(buf, ARENA_MAGIC);
+        u32put(&mut buf[4..], self.version);
+        nameput(&mut buf[8..8 + ANAME_SIZE], &self.name);
+        let mut p = 8 + ANAME_SIZE;
+        u32put(&mut buf[p..], self.diskstats.clumps);
+        u32put(&mut buf[p + 4..], self.diskstats.cclumps);
+        u32put(&mut buf[p + 8..], self.ctime);
+        u32put(&mut buf[p + 12..], self.wtime);
+        p += 16;
+        if self.version == ARENA_VERSION5 {
+            u32put(&mut buf[p..], self.clumpmagic);
+            p += 4;
+        }
+        u64put(&mut buf[p..], self.diskstats.used);
+        u64put(&mut buf[p + 8..], self.diskstats.uncsize);
+        buf[p + 16] = self.diskstats.sealed as u8;
+        p += 17;
+        if let Some(m) = &self.memstats {
+            buf[p] = 1;
+            u32put(&mut buf[p + 1..], m.clumps);
+            u32put(&mut buf[p + 5..], m.cclumps);
+            u64put(&mut buf[p + 9..], m.used);
+            u64put(&mut buf[p + 17..], m.uncsize);
+            buf[p + 25] = m.sealed as u8;
+        }
+        if let Some(score) = &self.score {
+            let n = buf.len();
+            buf[n - SCORE_SIZE..].copy_from_slice(&score.0);
+        }
+    }
+
+    /// Unpack from a whole trailer block
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is short or the magic or version is wrong
+    pub fn unpack(buf: &[u8]) -> Result<ArenaTrailer, String> {
+        if buf.len() < ARENA_SIZE4 {
+            return Err(format!("arena trailer of {} bytes", buf.len()));
+        }
+        let magic = u32get(buf);
+        if magic != ARENA_MAGIC {
+            return Err(format!("bad arena trailer magic {magic:#x}"));
+        }
+        let version = u32get(&buf[4..]);
+        let size = match version {
+            ARENA_VERSION4 => ARENA_SIZE4,
+            ARENA_VERSION5 => ARENA_SIZE5,
+            _ => return Err(format!("unknown arena version {version}")),
+        };
+        if buf.len() < size {
+            return Err(format!("arena trailer of {} bytes", buf.len()));
+        }
+        let name = nameget(&buf[8..8 + ANAME_SIZE]);
+        let mut p = 8 + ANAME_SIZE;
+        let mut diskstats = ArenaStats {
+            clumps: u32get(&buf[p..]),
+            cclumps: u32get(&buf[p + 4..]),
+            ..Default::default()
+        };
+        let ctime = u32get(&buf[p + 8..]);
+        let wtime = u32get(&buf[p + 12..]);
+        p += 16;
+        let mut clumpmagic = CLUMP_MAGIC;
+        if version == ARENA_VERSION5 {
+            clumpmagic = u32get(&buf[p..]);
+            p += 4;
+        }
+        diskstats.used = u64get(&buf[p..]);
+        diskstats.uncsize = u64get(&buf[p + 8..]);
+        diskstats.sealed = buf[p + 16] != 0;
+        p += 17;
+        let mut memstats = None;
+        if version == ARENA_VERSION5 && buf.len() >= ARENA_SIZE5A && buf[p] == 1 {
+            memstats = Some(ArenaStats {
+                clumps: u32get(&buf[p + 1..]),
+                cclumps: u32get(&buf[p + 5..]),
+                used: u64get(&buf[p + 9..]),
+                uncsize: u64get(&buf[p + 17..]),
+                sealed: buf[p + 25] != 0,
+            });
+        }
+        let mut score = None;
+        if memstats.as_ref().unwrap_or(&diskstats).sealed {
+            let n = buf.len();
+            let end = if memstats.is_some() {
+                ARENA_SIZE5A
+            } else {
+                size
+            };
+            if n < end + SCORE_SIZE {
+                return Err(format!("sealed arena trailer of {n} bytes"));
+            }
+            score = Some(Score(buf[n - SCORE_SIZE..].try_into().unwrap()));
+        }
+        Ok(ArenaTrailer {
+            version,
+            name,
+            ctime,
+            wtime,
+            clumpmagic,
+            diskstats,
+            memstats,
+            score,
+        })
+    }
+
+    /// The most recent statistics
+    pub fn stats(&self) -> &ArenaStats {
+        self.memstats.as_ref().unwrap_or(&self.diskstats)
+    }
+}
+
+/// An arena located in a partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arena {
+    /// offset of the arena in the partition
+    pub start: u64,
+    /// offset of the end of the arena
+    pub stop: u64,
+    pub head: ArenaHead,
+    pub trailer: ArenaTrailer,
+}
+
+impl Arena {
+    /// Offset of the data area in the partition
+    pub fn base(&self) -> u64 {
+        self.start + self.head.blocksize as u64
+    }
+
+    /// Size of the data area, clump directory included
+    pub fn size(&self) -> u64 {
+        self.stop - self.start - 2 * self.head.blocksize as u64
+    }
+
+    /// Number of clump directory entries in a block
+    pub fn clumpmax(&self) -> u64 {
+        self.head.blocksize as u64 / CLUMP_INFO_SIZE as u64
+    }
+
+    /// Offset of the directory entry of clump `n` in the partition
+    pub fn clumpinfo_offset(&self, n: u64) -> u64 {
+        let block = n / self.clumpmax();
+        let off = (n % self.clumpmax()) * CLUMP_INFO_SIZE as u64;
+        self.base() + self.size() - (block + 1) * self.head.blocksize as u64 + off
+    }
+}
+
+/// A clump as listed in an arena's directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClumpEntry {
+    /// offset of the clump in the arena's data area
+    pub addr: u64,
+    pub info: ClumpInfo,
+}
+
+/// Read-only view of an arena partition
+pub struct Partition<R> {
+    r: R,
+    pub part: ArenaPart,
+    pub map: Vec<AMap>,
+}
+
+impl<R: Read + Seek> Partition<R> {
+    /// Read the partition header and arena map from `r`
+    ///
+    /// # Errors
+    ///
+    /// If the header or map cannot be read or are malformed
+    pub fn open(mut r: R) -> Result<Partition<R>, String> {
+        let mut buf = [0u8; ARENA_PART_SIZE];
+        read_at(&mut r, PART_BLANK, &mut buf)?;
+        let part = ArenaPart::unpack(&buf)?;
+        let end = r.seek(SeekFrom::End(0)).map_err(|e| format!("seek: {e}"))?;
+        if part.tabbase() + part.tabsize() > end {
+            return Err(format!(
+                "arena map of {} bytes beyond the end of the partition",
+                part.tabsize()
+            ));
+        }
+        let mut tab = vec![0u8; part.tabsize().min(MAX_AMAP_SIZE) as usize];
+        read_at(&mut r, part.tabbase(), &mut tab)?;
+        let map = parse_amap(&tab)?;
+        Ok(Partition { r, part, map })
+    }
+
+    /// Read the header and trailer of the `n`th arena in the map
+    ///
+    /// # Errors
+    ///
+    /// If either cannot be read, is malformed or does not fit the map
+    pub fn arena(&mut self, n: usize) -> Result<Arena, String> {
+        let m = self.map.get(n).ok_or(format!("no arena {n}"))?.clone();
+        let blocksize = self.part.blocksize as u64;
+        if m.stop - m.start < 3 * blocksize {
+            return Err(format!("arena {} too small", m.name));
+        }
+        let mut buf = vec![0u8; blocksize as usize];
+        read_at(&mut self.r, m.start, &mut buf)?;
+        let head = ArenaHead::unpack(&buf)?;
+        if head.name != m.name || head.blocksize as u64 != blocksize {
+            return Err(format!("arena header doesn't match map entry {}", m.name));
+        }
+        read_at(&mut self.r, m.stop - blocksize, &mut buf)?;
+        let trailer = ArenaTrailer::unpack(&buf)?;
+        if trailer.name != m.name {
+            return Err(format!("arena trailer doesn't match map entry {}", m.name));
+        }
+        Ok(Arena {
+            start: m.start,
+            stop: m.stop,
+            head,
+            trailer,
+        })
+    }
+
+    /// List the clumps of an arena from its directory
+    ///
+    /// # Errors
+    ///
+    /// If the directory cannot be read or overlaps the clumps
+    pub fn clumps(&mut self, arena: &Arena) -> Result<Vec<ClumpEntry>, String> {
+        let n = arena.trailer.stats().clumps as u64;
+        let dirblocks = n.div_ceil(arena.clumpmax());
+        if arena.trailer.stats().used + dirblocks * arena.head.blocksize as u64 > arena.size() {
+            return Err(format!("arena {} overfull", arena.head.name));
+        }
+        let mut entries = Vec::with_capacity(n as usize);
+        let mut addr = 0;
+        let mut buf = [0u8; CLUMP_INFO_SIZE];
+        for i in 0..n {
+            read_at(&mut self.r, arena.clumpinfo_offset(i), &mut buf)?;
+            let info = ClumpInfo::unpack(&buf);
+            let size = CLUMP_SIZE as u64 + info.size as u64;
+            entries.push(ClumpEntry { addr, info });
+            addr += size;
+        }
+        Ok(entries)
+    }
+
+    /// Read the clump at `addr` in an arena's data area and decode it
+    ///
+    /// # Errors
+    ///
+    /// If the clump cannot be read, has the wrong magic or cannot be
+    /// decoded
+    pub fn read_clump(&mut self, arena: &Arena, addr: u64) -> Result<(Clump, Vec<u8>), String> {
+        let c = self.read_clump_header(arena, addr)?;
+        let mut buf = vec![0u8; CLUMP_SIZE + c.info.size as usize];
+        read_at(&mut self.r, arena.base() + addr, &mut buf)?;
+        clump::decode(&buf)
+    }
+
+    /// Read only the header of the clump at `addr`
+    ///
+    /// # Errors
+    ///
+    /// If the header cannot be read, is malformed or has the wrong
+    /// magic
+    pub fn read_clump_header(&mut self, arena: &Arena, addr: u64) -> Result<Clump, String> {
+        let mut head = [0u8; CLUMP_SIZE];
+        if addr + CLUMP_SIZE as u64 > arena.size() {
+            return Err(format!("clump address {addr} outside arena"));
+        }
+        read_at(&mut self.r, arena.base() + addr, &mut head)?;
+        let c = Clump::unpack(&head)?;
+        if c.magic != arena.head.clumpmagic {
+            return Err(format!("bad clump magic {:#x} at {addr}", c.magic));
+        }
+        Ok(c)
+    }
+
+    /// Iterate over all blocks stored in the partition
+    pub fn blocks(&mut self) -> Blocks<'_, R> {
+        Blocks {
+            part: self,
+            arena: 0,
+            current: None,
+            entries: Vec::new().into_iter(),
+            failed: false,
+        }
+    }
+}
+
+/// Iterator over the blocks of a [`Partition`], arena by arena
+///
+/// Yields the directory entry of each clump together with the decoded
+/// block. Stops after the first error.
+pub struct Blocks<'a, R> {
+    part: &'a mut Partition<R>,
+    /// next arena in the map
+    arena: usize,
+    current: Option<Arena>,
+    entries: std::vec::IntoIter<ClumpEntry>,
+    failed: bool,
+}
+
+impl<R: Read + Seek> Iterator for Blocks<'_, R> {
+    type Item = Result<(ClumpEntry, Vec<u8>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            if let (Some(arena), Some(entry)) = (&self.current, self.entries.next()) {
+                let rv = match self.part.read_clump(arena, entry.addr) {
+                    Ok((c, _)) if c.info != entry.info => {
+                        Err(format!("clump at {} doesn't match directory", entry.addr))
+                    }
+                    Ok((_, data)) => Ok((entry, data)),
+                    Err(e) => Err(e),
+                };
+                self.failed = rv.is_err();
+                return Some(rv);
+            }
+            if self.arena >= self.part.map.len() {
+                return None;
+            }
+            let rv = self.part.arena(self.arena).and_then(|arena| {
+                let entries = self.part.clumps(&arena)?;
+                Ok((arena, entries))
+            });
+            self.arena += 1;
+            match rv {
+                Ok((arena, entries)) => {
+                    self.current = Some(arena);
+                    self.entries = entries.into_iter();
+                }
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Builds a version 5 arena from blocks
+///
+/// Each block is appended as a clump, whack compressed when that
+/// makes it smaller. The clump directory and the trailer statistics
+/// are written by [`ArenaWriter::finish`], which can also seal the
+/// arena. The result is laid out as in a partition, so it can be
+/// copied into one or loaded into venti.
+pub struct ArenaWriter<W> {
+    w: W,
+    arena: Arena,
+    dir: Vec<ClumpInfo>,
+}
+
+impl<W: Read + Write + Seek> ArenaWriter<W> {
+    /// Start an arena of `size` bytes at offset `start` of `w`
+    ///
+    /// # Errors
+    ///
+    /// If the sizes don't fit together or the header cannot be written
+    pub fn new(
+        w: W,
+        start: u64,
+        name: &str,
+        size: u64,
+        blocksize: u32,
+        clumpmagic: u32,
+        ctime: u32,
+    ) -> Result<ArenaWriter<W>, String> {
+        if name.len() >= ANAME_SIZE {
+            return Err(format!("arena name too long: {name}"));
+        }
+        if !blocksize.is_power_of_two() || (blocksize as usize) < MIN_BLOCK_SIZE {
+            return Err(format!("bad arena block size {blocksize}"));
+        }
+        if !size.is_multiple_of(blocksize as u64) || size < 3 * blocksize as u64 {
+            return Err(format!("bad arena size {size}"));
+        }
+        let head = ArenaHead {
+            version: ARENA_VERSION5,
+            name: name.to_string(),
+            clumpmagic,
+            blocksize,
+            size,
+        };
+        let trailer = ArenaTrailer {
+            version: ARENA_VERSION5,
+            name: name.to_string(),
+            ctime,
+            wtime: ctime,
+            clumpmagic,
+            diskstats: ArenaStats::default(),
+            memstats: None,
+            score: None,
+        };
+        let mut aw = ArenaWriter {
+            w,
+            arena: Arena {
+                start,
+                stop: start + size,
+                head,
+                trailer,
+            },
+            dir: Vec::new(),
+        };
+        let mut buf = vec![0u8; blocksize as usize];
+        aw.arena.head.pack(&mut buf);
+        write_at(&mut aw.w, start, &buf)?;
+        Ok(aw)
+    }
+
+    /// Append a block, returning where its clump went
+    ///
+    /// # Errors
+    ///
+    /// If the block is too large, the arena is full or the clump
+    /// cannot be written
+    pub fn write(&mut self, kind: u8, data: &[u8], time: u32) -> Result<ClumpEntry, String> {
+        let buf = clump::encode(kind, data, self.arena.head.clumpmagic, 0, time)?;
+        let c = Clump::unpack(&buf)?;
+        let stats = &self.arena.trailer.diskstats;
+        let dirblocks = (self.dir.len() as u64 + 1).div_ceil(self.arena.clumpmax());
+        let free = self
+            .arena
+            .size()
+            .saturating_sub(dirblocks * self.arena.head.blocksize as u64);
+        if stats.used + buf.len() as u64 > free {
+            return Err(format!("arena {} full", self.arena.head.name));
+        }
+        let addr = stats.used;
+        write_at(&mut self.w, self.arena.base() + addr, &buf)?;
+        let stats = &mut self.arena.trailer.diskstats;
+        stats.clumps += 1;
+        if c.encoding == Encoding::Compressed {
+            stats.cclumps += 1;
+        }
+        stats.used += buf.len() as u64;
+        stats.uncsize += data.len() as u64;
+        self.arena.trailer.wtime = self.arena.trailer.wtime.max(time);
+        self.dir.push(c.info.clone());
+        Ok(ClumpEntry { addr, info: c.info })
+    }
+
+    /// Statistics of the clumps written so far
+    pub fn stats(&self) -> &ArenaStats {
+        &self.arena.trailer.diskstats
+    }
+
+    /// Write the clump directory and trailer, and return the output
+    ///
+    /// A sealed arena takes no more clumps and carries the SHA-1 of
+    /// its contents, computed with the score itself zeroed, in the
+    /// last bytes of the trailer.
+    ///
+    /// # Errors
+    ///
+    /// If writing or reading back fails
+    pub fn finish(mut self, seal: bool) -> Result<(Arena, W), String> {
+        let blocksize = self.arena.head.blocksize as usize;
+        let clumpmax = self.arena.clumpmax() as usize;
+        for (n, infos) in self.dir.chunks(clumpmax).enumerate() {
+            let mut buf = vec![0u8; blocksize];
+            for (i, info) in infos.iter().enumerate() {
+                info.pack(&mut buf[i * CLUMP_INFO_SIZE..]);
+            }
+            let off = self.arena.clumpinfo_offset((n * clumpmax) as u64);
+            write_at(&mut self.w, off, &buf)?;
+        }
+        self.arena.trailer.diskstats.sealed = seal;
+        let tail = self.arena.stop - blocksize as u64;
+        let mut buf = vec![0u8; blocksize];
+        self.arena.trailer.pack(&mut buf);
+        write_at(&mut self.w, tail, &buf)?;
+        if seal {
+            let mut hash = Sha1::new();
+            let mut off = self.arena.start;
+            while off < self.arena.stop {
+                read_at(&mut self.w, off, &mut buf)?;
+                hash.update(&buf);
+                off += blocksize as u64;
+            }
+            let score = Score(hash.finalize());
+            write_at(&mut self.w, self.arena.stop - SCORE_SIZE as u64, &score.0)?;
+            self.arena.trailer.score = Some(score);
+        }
+        self.w.flush().map_err(|e| e.to_string())?;
+        Ok((self.arena, self.w))
+    }
+}
+
+/// write `buf` at offset `off` of `w`
+fn write_at<W: Write + Seek>(w: &mut W, off: u64, buf: &[u8]) -> Result<(), String> {
+    w.seek(SeekFrom::Start(off))
+        .and_then(|_| w.write_all(buf))
+        .map_err(|e| format!("write at {off}: {e}"))
+}
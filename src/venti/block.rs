@@ -0,0 +1,107 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti block types and zero truncation
+//!
+//! Venti clients strip the trailing zeros from a block before writing
+//! it and put them back after reading, so a block's score is that of
+//! its truncated form. Data blocks lose their trailing zero bytes,
+//! pointer blocks their trailing zero scores, and root blocks are
+//! stored whole. Blocks must be truncated before they are whacked
+//! for the clumps and scores to match those of the C venti.
+//!
+//! Arenas, index entries and the wire still carry the older block
+//! types, which have a single set of pointer types shared by data and
+//! directory trees. [`to_disk_type`] and [`from_disk_type`] convert
+//! between the two, as `vttodisktype` and `vtfromdisktype` do.
+use super::score::{SCORE_SIZE, Score};
+
+/// data block; lower three bits give the depth of pointer blocks
+pub const DATA_TYPE: u8 = 0;
+/// directory block of entries; lower three bits give the depth
+pub const DIR_TYPE: u8 = 8;
+/// root block
+pub const ROOT_TYPE: u8 = 16;
+/// mask of the pointer depth in a block type
+pub const DEPTH_MASK: u8 = 7;
+
+/// type of corrupt blocks and of types with no counterpart
+pub const CORRUPT_TYPE: u8 = 0xff;
+
+/// old type of data blocks
+const DISK_DATA_TYPE: u8 = 13;
+/// old type of directory blocks
+const DISK_DIR_TYPE: u8 = 2;
+/// old type of root blocks
+const DISK_ROOT_TYPE: u8 = 1;
+/// old type of pointer blocks of depth 1, followed by the deeper ones
+const DISK_POINTER_TYPE: u8 = 3;
+
+/// Old type of a block of type `kind`, as stored on disk and sent on the wire
+pub fn to_disk_type(kind: u8) -> u8 {
+    match kind {
+        DATA_TYPE => DISK_DATA_TYPE,
+        DIR_TYPE => DISK_DIR_TYPE,
+        ROOT_TYPE => DISK_ROOT_TYPE,
+        k if k < ROOT_TYPE => DISK_POINTER_TYPE + (k & DEPTH_MASK) - 1,
+        _ => CORRUPT_TYPE,
+    }
+}
+
+/// Block type of old type `kind`
+///
+/// The old pointer types do not say what the tree holds, so they
+/// come back as directory pointer types. Compare types with
+/// [`same_type`] where either may have been through the disk.
+pub fn from_disk_type(kind: u8) -> u8 {
+    match kind {
+        DISK_DATA_TYPE => DATA_TYPE,
+        DISK_DIR_TYPE => DIR_TYPE,
+        DISK_ROOT_TYPE => ROOT_TYPE,
+        k if (DISK_POINTER_TYPE..DISK_POINTER_TYPE + DEPTH_MASK).contains(&k) => {
+            DIR_TYPE + k - DISK_POINTER_TYPE + 1
+        }
+        _ => CORRUPT_TYPE,
+    }
+}
+
+/// Whether types `a` and `b` are stored as the same old type
+pub fn same_type(a: u8, b: u8) -> bool {
+    to_disk_type(a) == to_disk_type(b)
+}
+
+/// Whether blocks of type `kind` hold scores
+pub fn is_pointer(kind: u8) -> bool {
+    kind & DEPTH_MASK != 0
+}
+
+/// Strip the trailing zeros of a block of type `kind`
+pub fn zero_truncate(kind: u8, data: &[u8]) -> &[u8] {
+    if kind == ROOT_TYPE {
+        return data;
+    }
+    if is_pointer(kind) {
+        let mut n = data.len();
+        while n >= SCORE_SIZE && data[n - SCORE_SIZE..n] == Score::ZERO.0 {
+            n -= SCORE_SIZE;
+        }
+        return &data[..n];
+    }
+    let n = data.iter().rposition(|&c| c != 0).map_or(0, |n| n + 1);
+    &data[..n]
+}
+
+/// Pad a truncated block of type `kind` back to `size` bytes
+///
+/// Pointer blocks are padded with zero scores from their current
+/// length, as far as whole scores fit, and zero bytes after that. A
+/// block longer than `size` is left alone.
+pub fn zero_extend(kind: u8, data: &mut Vec<u8>, size: usize) {
+    if data.len() >= size {
+        return;
+    }
+    if is_pointer(kind) {
+        while data.len() + SCORE_SIZE <= size {
+            data.extend_from_slice(&Score::ZERO.0);
+        }
+    }
+    data.resize(size, 0);
+}
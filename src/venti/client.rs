@@ -0,0 +1,278 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti client
+//!
+//! Talks the venti protocol over any stream, one request at a time.
+//! Blocks can be kept in a local cache, whack compressed as clumps
+//! the same way the local server stores them. The cache holds a
+//! bounded number of bytes and drops the least recently used blocks.
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufReader, Read, Write};
+
+use super::block::{zero_extend, zero_truncate};
+use super::clump::{self, CLUMP_MAGIC, MAX_LUMP_SIZE};
+use super::proto::{Fcall, Msg, read_fcall, read_version, write_fcall, write_version};
+use super::score::Score;
+use super::store::decode_verified;
+
+/// Connection to a venti server
+pub struct Client<C: Read + Write> {
+    conn: BufReader<C>,
+    tag: u8,
+    /// session id the server handed out
+    pub sid: String,
+    /// clumps of blocks seen, if caching
+    cache: Option<Cache>,
+}
+
+/// Most recently used clumps of a client
+struct Cache {
+    /// most bytes of clumps kept
+    max: usize,
+    /// bytes of clumps kept
+    size: usize,
+    /// each clump and its time of last use
+    clumps: HashMap<Score, (Vec<u8>, u64)>,
+    /// blocks by time of last use
+    lru: BTreeMap<u64, Score>,
+    clock: u64,
+}
+
+impl<C: Read + Write> Client<C> {
+    /// Exchange versions and say hello as `uid`
+    ///
+    /// # Errors
+    ///
+    /// If the connection fails or the server refuses
+    pub fn connect(conn: C, uid: &str) -> Result<Client<C>, String> {
+        let mut conn = BufReader::new(conn);
+        write_version(conn.get_mut())?;
+        read_version(&mut conn)?;
+        let mut c = Client {
+            conn,
+            tag: 0,
+            sid: String::new(),
+            cache: None,
+        };
+        let hello = Msg::Thello {
+            version: String::from("02"),
+            uid: String::from(uid),
+            strength: 0,
+            crypto: Vec::new(),
+            codec: Vec::new(),
+        };
+        match c.rpc(hello)? {
+            Msg::Rhello { sid, .. } => c.sid = sid,
+            m => return Err(format!("unexpected reply {m:?} to hello")),
+        }
+        Ok(c)
+    }
+
+    /// Keep whack compressed copies of the blocks read or written
+    ///
+    /// The cache holds at most `max` bytes of clumps, dropping the
+    /// least recently used ones to make room.
+    pub fn enable_cache(&mut self, max: usize) {
+        let cache = self.cache.get_or_insert_with(|| Cache {
+            max,
+            size: 0,
+            clumps: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+        });
+        cache.max = max;
+        cache.shrink(0);
+    }
+
+    /// Number of bytes the cache holds
+    pub fn cache_size(&self) -> usize {
+        self.cache.as_ref().map_or(0, |c| c.size)
+    }
+
+    /// Check the server is alive
+    ///
+    /// # Errors
+    ///
+    /// If the server does not answer
+    pub fn ping(&mut self) -> Result<(), String> {
+        match self.rpc(Msg::Tping)? {
+            Msg::Rping => Ok(()),
+            m => Err(format!("unexpected reply {m:?} to ping")),
+        }
+    }
+
+    /// Fetch the block with `score` and type `kind`, at most `max` bytes
+    ///
+    /// The zero score is the empty block and needs no request.
+    ///
+    /// # Errors
+    ///
+    /// If the server has no such block, the block is larger than
+    /// `max` bytes or the server sends one that does not match its score
+    pub fn read(&mut self, score: &Score, kind: u8, max: usize) -> Result<Vec<u8>, String> {
+        if *score == Score::ZERO {
+            return Ok(Vec::new());
+        }
+        let max = max.min(MAX_LUMP_SIZE);
+        if let Some(c) = self.cache.as_mut().and_then(|c| c.get(score))
+            && let Some(data) = decode_verified(c, score, kind)?
+        {
+            if data.len() > max {
+                return Err(format!("block {score} larger than {max} bytes"));
+            }
+            return Ok(data);
+        }
+        let msg = Msg::Tread {
+            score: *score,
+            kind,
+            count: max as u16,
+        };
+        let data = match self.rpc(msg)? {
+            Msg::Rread(data) => data,
+            m => return Err(format!("unexpected reply {m:?} to read")),
+        };
+        if data.len() > max {
+            return Err(format!(
+                "server sent {} bytes for {score}, at most {max} asked",
+                data.len()
+            ));
+        }
+        if Score::of(&data) != *score {
+            return Err(format!("server sent wrong block for {score}"));
+        }
+        self.remember(kind, &data)?;
+        Ok(data)
+    }
+
+    /// Store `data` as a block of type `kind` and return its score
+    ///
+    /// # Errors
+    ///
+    /// If the server refuses or returns the wrong score
+    pub fn write(&mut self, kind: u8, data: &[u8]) -> Result<Score, String> {
+        let score = Score::of(data);
+        let msg = Msg::Twrite {
+            kind,
+            data: data.to_vec(),
+        };
+        match self.rpc(msg)? {
+            Msg::Rwrite(s) if s == score => {}
+            Msg::Rwrite(s) => return Err(format!("server returned score {s} for {score}")),
+            m => return Err(format!("unexpected reply {m:?} to write")),
+        }
+        self.remember(kind, data)?;
+        Ok(score)
+    }
+
+    /// Store a block of type `kind` without its trailing zeros
+    ///
+    /// The score is that of the truncated block, as the C venti
+    /// clients compute it.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Client::write`]
+    pub fn write_block(&mut self, kind: u8, data: &[u8]) -> Result<Score, String> {
+        self.write(kind, zero_truncate(kind, data))
+    }
+
+    /// Fetch a block written by [`Client::write_block`], padded to `size` bytes
+    ///
+    /// # Errors
+    ///
+    /// As for [`Client::read`]
+    pub fn read_block(&mut self, score: &Score, kind: u8, size: usize) -> Result<Vec<u8>, String> {
+        let mut data = self.read(score, kind, size)?;
+        zero_extend(kind, &mut data, size);
+        Ok(data)
+    }
+
+    /// Ask the server to make the blocks written so far durable
+    ///
+    /// # Errors
+    ///
+    /// If the server fails to sync
+    pub fn sync(&mut self) -> Result<(), String> {
+        match self.rpc(Msg::Tsync)? {
+            Msg::Rsync => Ok(()),
+            m => Err(format!("unexpected reply {m:?} to sync")),
+        }
+    }
+
+    /// End the session and return the connection
+    ///
+    /// # Errors
+    ///
+    /// If the goodbye cannot be sent
+    pub fn goodbye(mut self) -> Result<C, String> {
+        let f = Fcall {
+            tag: self.tag,
+            msg: Msg::Tgoodbye,
+        };
+        write_fcall(self.conn.get_mut(), &f)?;
+        Ok(self.conn.into_inner())
+    }
+
+    /// send a request and wait for its reply
+    fn rpc(&mut self, msg: Msg) -> Result<Msg, String> {
+        let tag = self.tag;
+        self.tag = self.tag.wrapping_add(1);
+        write_fcall(self.conn.get_mut(), &Fcall { tag, msg })?;
+        let f = read_fcall(&mut self.conn)?.ok_or("server hung up")?;
+        if f.tag != tag {
+            return Err(format!("reply tag {} to request {tag}", f.tag));
+        }
+        match f.msg {
+            Msg::Rerror(e) => Err(e),
+            msg => Ok(msg),
+        }
+    }
+
+    /// add a block to the cache, if caching
+    fn remember(&mut self, kind: u8, data: &[u8]) -> Result<(), String> {
+        if let Some(cache) = &mut self.cache {
+            let c = clump::encode(kind, data, CLUMP_MAGIC, 0, 0)?;
+            cache.insert(Score::of(data), c);
+        }
+        Ok(())
+    }
+}
+
+impl Cache {
+    /// the clump of `score`, marked as just used
+    fn get(&mut self, score: &Score) -> Option<&[u8]> {
+        let (c, t) = self.clumps.get_mut(score)?;
+        self.lru.remove(t);
+        self.clock += 1;
+        *t = self.clock;
+        self.lru.insert(self.clock, *score);
+        Some(c)
+    }
+
+    /// keep clump `c` of `score`, if it fits
+    fn insert(&mut self, score: Score, c: Vec<u8>) {
+        if let Some((old, t)) = self.clumps.remove(&score) {
+            self.lru.remove(&t);
+            self.size -= old.len();
+        }
+        if c.len() > self.max {
+            return;
+        }
+        self.shrink(c.len());
+        self.clock += 1;
+        self.size += c.len();
+        self.clumps.insert(score, (c, self.clock));
+        self.lru.insert(self.clock, score);
+    }
+
+    /// drop the least recently used clumps until `room` more bytes fit
+    fn shrink(&mut self, room: usize) {
+        while self.size + room > self.max {
+            let Some((_, old)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some((c, _)) = self.clumps.remove(&old) {
+                self.size -= c.len();
+            }
+        }
+    }
+}
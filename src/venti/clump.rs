@@ -0,0 +1,189 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti clumps
+//!
+//! A clump is a block as venti stores it in an arena: a fixed size
+//! header followed by the block data, whack compressed if that made
+//! it smaller.
+use super::block::{from_disk_type, to_disk_type};
+use super::score::Score;
+use super::{u16get, u16put, u32get, u32put};
+use crate::unwhack::unwhack;
+use crate::whack::whackblock;
+
+/// clump header magic of version 4 arenas; version 5 arenas pick their own
+pub const CLUMP_MAGIC: u32 = 0xd15cb10c;
+/// magic of unused space after the last clump
+pub const CLUMP_FREE_MAGIC: u32 = 0;
+/// size of a packed [`ClumpInfo`]
+pub const CLUMP_INFO_SIZE: usize = 25;
+/// size of a packed [`Clump`] header
+pub const CLUMP_SIZE: usize = 38;
+/// largest block venti accepts
+pub const MAX_LUMP_SIZE: usize = 56 * 1024;
+
+/// How the data following a clump header is stored
+///
+/// The values are those of venti; any other value is rejected when a
+/// header is parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// plain
+    None = 1,
+    /// whack compressed
+    Compressed = 2,
+}
+
+/// Clump description, also kept in the arena's clump directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClumpInfo {
+    /// block type
+    pub kind: u8,
+    /// stored size of the data
+    pub size: u16,
+    /// size of the data once decoded
+    pub uncsize: u16,
+    /// SHA-1 of the decoded data
+    pub score: Score,
+}
+
+/// Clump header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clump {
+    pub magic: u32,
+    pub info: ClumpInfo,
+    pub encoding: Encoding,
+    /// id of the writer
+    pub creator: u32,
+    /// time the clump was written
+    pub time: u32,
+}
+
+impl ClumpInfo {
+    /// Pack into the first [`CLUMP_INFO_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        buf[0] = to_disk_type(self.kind);
+        u16put(&mut buf[1..], self.size);
+        u16put(&mut buf[3..], self.uncsize);
+        buf[5..25].copy_from_slice(&self.score.0);
+    }
+
+    /// Unpack from the first [`CLUMP_INFO_SIZE`] bytes of `buf`
+    pub fn unpack(buf: &[u8]) -> ClumpInfo {
+        ClumpInfo {
+            kind: from_disk_type(buf[0]),
+            size: u16get(&buf[1..]),
+            uncsize: u16get(&buf[3..]),
+            score: Score(buf[5..25].try_into().unwrap()),
+        }
+    }
+}
+
+impl Clump {
+    /// Pack into the first [`CLUMP_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        u32put(buf, self.magic);
+        self.info.pack(&mut buf[4..]);
+        buf[29] = self.encoding as u8;
+        u32put(&mut buf[30..], self.creator);
+        u32put(&mut buf[34..], self.time);
+    }
+
+    /// Unpack from the first [`CLUMP_SIZE`] bytes of `buf`
+    ///
+    /// # Errors
+    ///
+    /// If the encoding is unknown
+    pub fn unpack(buf: &[u8]) -> Result<Clump, String> {
+        let encoding = match buf[29] {
+            1 => Encoding::None,
+            2 => Encoding::Compressed,
+            e => return Err(format!("unknown clump encoding {e}")),
+        };
+        Ok(Clump {
+            magic: u32get(buf),
+            info: ClumpInfo::unpack(&buf[4..]),
+            encoding,
+            creator: u32get(&buf[30..]),
+            time: u32get(&buf[34..]),
+        })
+    }
+}
+
+/// Make a clump from a block
+///
+/// Compresses `data` with [`whackblock`] and stores it plain if
+/// that did not make it smaller. Returns the packed header
+/// followed by the stored data.
+///
+/// # Errors
+///
+/// If the block is larger than [`MAX_LUMP_SIZE`]
+pub fn encode(
+    kind: u8,
+    data: &[u8],
+    magic: u32,
+    creator: u32,
+    time: u32,
+) -> Result<Vec<u8>, String> {
+    if data.len() > MAX_LUMP_SIZE {
+        return Err(format!("block too large: {} bytes", data.len()));
+    }
+    let compressed = whackblock(data);
+    let (encoding, stored) = match &compressed {
+        Some(c) => (Encoding::Compressed, c.as_slice()),
+        None => (Encoding::None, data),
+    };
+    let clump = Clump {
+        magic,
+        info: ClumpInfo {
+            kind,
+            size: stored.len() as u16,
+            uncsize: data.len() as u16,
+            score: Score::of(data),
+        },
+        encoding,
+        creator,
+        time,
+    };
+    let mut buf = vec![0; CLUMP_SIZE + stored.len()];
+    clump.pack(&mut buf);
+    buf[CLUMP_SIZE..].copy_from_slice(stored);
+    Ok(buf)
+}
+
+/// Read a clump and decode its data
+///
+/// `buf` holds the header and at least the stored data. The data is
+/// decompressed with [`unwhack`] to the recorded uncompressed size.
+///
+/// # Errors
+///
+/// If the header is damaged, the data is cut short or cannot be
+/// decompressed to the recorded size
+pub fn decode(buf: &[u8]) -> Result<(Clump, Vec<u8>), String> {
+    if buf.len() < CLUMP_SIZE {
+        return Err(String::from("short clump header"));
+    }
+    let clump = Clump::unpack(buf)?;
+    let size = clump.info.size as usize;
+    let uncsize = clump.info.uncsize as usize;
+    let stored = buf
+        .get(CLUMP_SIZE..CLUMP_SIZE + size)
+        .ok_or("short clump data")?;
+    let data = match clump.encoding {
+        Encoding::None => {
+            if size != uncsize {
+                return Err(format!("plain clump of {size} bytes claims {uncsize}"));
+            }
+            stored.to_vec()
+        }
+        Encoding::Compressed => unwhack(stored, uncsize)?,
+    };
+    if data.len() != uncsize {
+        return Err(format!(
+            "clump decoded to {} bytes instead of {uncsize}",
+            data.len()
+        ));
+    }
+    Ok((clump, data))
+}
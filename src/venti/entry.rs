@@ -0,0 +1,359 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti entries and roots
+//!
+//! An entry describes a stream of blocks as a tree: data blocks of
+//! `dsize` bytes at the bottom and pointer blocks of at most `psize`
+//! bytes of scores above them, `depth` levels deep. A root block
+//! names the score of a directory block holding the top entries.
+use std::io::Read;
+
+use super::block::{DATA_TYPE, DEPTH_MASK, DIR_TYPE, zero_extend, zero_truncate};
+use super::score::{SCORE_SIZE, Score};
+use super::store::{BlockStore, WriteStore};
+use super::{nameget, nameput, u16get, u16put, u32get, u32put};
+
+/// size of a packed [`Entry`]
+pub const ENTRY_SIZE: usize = 40;
+/// size of a packed [`Root`]
+pub const ROOT_SIZE: usize = 300;
+pub const ROOT_VERSION: u16 = 2;
+/// size of the name and type fields of a root
+pub const ROOT_NAME_SIZE: usize = 128;
+
+/// most bytes reserved up front by [`Entry::read_all`], whatever the entry's size
+const READ_RESERVE: usize = 1024 * 1024;
+
+/// the entry is in use
+pub const ENTRY_ACTIVE: u8 = 1;
+/// the entry's blocks hold entries
+pub const ENTRY_DIR: u8 = 1 << 1;
+pub const ENTRY_DEPTH_SHIFT: u8 = 2;
+pub const ENTRY_DEPTH_MASK: u8 = 7 << ENTRY_DEPTH_SHIFT;
+/// the score is a local block address, used by fossil
+pub const ENTRY_LOCAL: u8 = 1 << 5;
+/// not to be archived, used by fossil
+pub const ENTRY_NO_ARCHIVE: u8 = 1 << 7;
+
+/// Stream of blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// generation, bumped when the entry is reused
+    pub generation: u32,
+    /// size of pointer blocks
+    pub psize: u16,
+    /// size of data blocks
+    pub dsize: u16,
+    /// type of the top block: [`DATA_TYPE`] or [`DIR_TYPE`] plus the depth
+    pub kind: u8,
+    /// the flags other than the type
+    pub flags: u8,
+    /// length of the stream in bytes
+    pub size: u64,
+    /// score of the top block
+    pub score: Score,
+}
+
+/// Root block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Root {
+    pub name: String,
+    /// kind of tree, such as "vac"
+    pub kind: String,
+    /// score of the directory block of top entries
+    pub score: Score,
+    /// largest block in the tree
+    pub blocksize: u16,
+    /// previous root, or the zero score
+    pub prev: Score,
+}
+
+impl Entry {
+    /// Pack into the first [`ENTRY_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        u32put(buf, self.generation);
+        u16put(&mut buf[4..], self.psize);
+        u16put(&mut buf[6..], self.dsize);
+        let mut flags = self.flags & !(ENTRY_DIR | ENTRY_DEPTH_MASK);
+        if self.kind & !DEPTH_MASK == DIR_TYPE {
+            flags |= ENTRY_DIR;
+        }
+        flags |= self.depth() << ENTRY_DEPTH_SHIFT;
+        buf[8] = flags;
+        buf[9..14].fill(0);
+        buf[14..20].copy_from_slice(&self.size.to_be_bytes()[2..]);
+        buf[20..40].copy_from_slice(&self.score.0);
+    }
+
+    /// Unpack from the first [`ENTRY_SIZE`] bytes of `buf`
+    ///
+    /// Fossil packs the archive flag, snapshot and tag before the
+    /// block address of a local entry; those are dropped to leave a
+    /// plain local score.
+    pub fn unpack(buf: &[u8]) -> Entry {
+        let flags = buf[8];
+        let base = if flags & ENTRY_DIR != 0 {
+            DIR_TYPE
+        } else {
+            DATA_TYPE
+        };
+        let mut size = [0u8; 8];
+        size[2..].copy_from_slice(&buf[14..20]);
+        Entry {
+            generation: u32get(buf),
+            psize: u16get(&buf[4..]),
+            dsize: u16get(&buf[6..]),
+            kind: base + ((flags & ENTRY_DEPTH_MASK) >> ENTRY_DEPTH_SHIFT),
+            flags: flags & !(ENTRY_DIR | ENTRY_DEPTH_MASK),
+            size: u64::from_be_bytes(size),
+            score: if flags & ENTRY_LOCAL != 0 {
+                Score::local(u32get(&buf[36..]))
+            } else {
+                Score(buf[20..40].try_into().unwrap())
+            },
+        }
+    }
+
+    /// Number of pointer levels above the data blocks
+    pub fn depth(&self) -> u8 {
+        self.kind & DEPTH_MASK
+    }
+
+    /// Number of scores in a pointer block
+    pub fn fanout(&self) -> u64 {
+        (self.psize as usize / SCORE_SIZE) as u64
+    }
+
+    /// Fetch data block number `bn`, padded to `dsize` bytes
+    ///
+    /// Walks down the pointer blocks from the top score. Zero scores
+    /// stand for blocks of zeros.
+    ///
+    /// # Errors
+    ///
+    /// If a block is missing or damaged, or `bn` is out of reach
+    pub fn block<S: BlockStore>(&self, store: &mut S, bn: u64) -> Result<Vec<u8>, String> {
+        let depth = self.depth();
+        let fanout = self.fanout();
+        if depth > 0 && fanout == 0 {
+            return Err(format!("pointer blocks of {} bytes", self.psize));
+        }
+        let mut index = Vec::with_capacity(depth as usize);
+        let mut rest = bn;
+        for _ in 0..depth {
+            index.push(rest % fanout);
+            rest /= fanout;
+        }
+        if rest != 0 {
+            return Err(format!("block {bn} beyond depth {depth}"));
+        }
+        let mut score = self.score;
+        let mut kind = self.kind;
+        for i in index.into_iter().rev() {
+            let p = fetch(store, &score, kind, self.psize as usize)?;
+            let off = i as usize * SCORE_SIZE;
+            score = Score(p[off..off + SCORE_SIZE].try_into().unwrap());
+            kind -= 1;
+        }
+        fetch(store, &score, kind, self.dsize as usize)
+    }
+
+    /// Store the contents of `r` as a tree and return its entry
+    ///
+    /// Cuts the input into data blocks of `dsize` bytes, of type
+    /// `base`, and stores them and the pointer blocks above them
+    /// without their trailing zeros. An empty stream is the zero
+    /// score.
+    ///
+    /// # Errors
+    ///
+    /// If reading or storing a block fails, or the tree gets too deep
+    pub fn write<S: WriteStore, R: Read>(
+        store: &mut S,
+        base: u8,
+        r: &mut R,
+        psize: u16,
+        dsize: u16,
+    ) -> Result<Entry, String> {
+        let fanout = psize as usize / SCORE_SIZE;
+        if fanout < 2 || dsize == 0 {
+            return Err(format!("bad block sizes {psize}/{dsize}"));
+        }
+        let mut tree = Tree {
+            base,
+            fanout,
+            levels: Vec::new(),
+        };
+        let mut size = 0;
+        let mut buf = vec![0u8; dsize as usize];
+        loop {
+            let n = read_full(r, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            size += n as u64;
+            let score = store.write(base, zero_truncate(base, &buf[..n]))?;
+            tree.push(store, 0, score)?;
+            if n < buf.len() {
+                break;
+            }
+        }
+        let (depth, score) = tree.finish(store)?;
+        Ok(Entry {
+            generation: 0,
+            psize,
+            dsize,
+            kind: base + depth,
+            flags: ENTRY_ACTIVE,
+            size,
+            score,
+        })
+    }
+
+    /// Read the whole stream
+    ///
+    /// # Errors
+    ///
+    /// As for [`Entry::block`]
+    pub fn read_all<S: BlockStore>(&self, store: &mut S) -> Result<Vec<u8>, String> {
+        let size = usize::try_from(self.size).map_err(|_| String::from("stream too large"))?;
+        if size > 0 && self.dsize == 0 {
+            return Err(String::from("data blocks of 0 bytes"));
+        }
+        let mut data = Vec::with_capacity(size.min(READ_RESERVE));
+        let mut bn = 0;
+        while data.len() < size {
+            data.extend_from_slice(&self.block(store, bn)?);
+            bn += 1;
+        }
+        data.truncate(size);
+        Ok(data)
+    }
+}
+
+impl Root {
+    /// Pack into the first [`ROOT_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        u16put(buf, ROOT_VERSION);
+        let p = 2 + 2 * ROOT_NAME_SIZE;
+        nameput(&mut buf[2..2 + ROOT_NAME_SIZE], &self.name);
+        nameput(&mut buf[2 + ROOT_NAME_SIZE..p], &self.kind);
+        buf[p..p + SCORE_SIZE].copy_from_slice(&self.score.0);
+        u16put(&mut buf[p + SCORE_SIZE..], self.blocksize);
+        buf[p + SCORE_SIZE + 2..p + 2 * SCORE_SIZE + 2].copy_from_slice(&self.prev.0);
+    }
+
+    /// Unpack from the first [`ROOT_SIZE`] bytes of `buf`
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is short or the version is not supported
+    pub fn unpack(buf: &[u8]) -> Result<Root, String> {
+        if buf.len() < ROOT_SIZE {
+            return Err(format!("root block of {} bytes", buf.len()));
+        }
+        let version = u16get(buf);
+        if version != ROOT_VERSION {
+            return Err(format!("unsupported root version {version}"));
+        }
+        let p = 2 + 2 * ROOT_NAME_SIZE;
+        Ok(Root {
+            name: nameget(&buf[2..2 + ROOT_NAME_SIZE]),
+            kind: nameget(&buf[2 + ROOT_NAME_SIZE..p]),
+            score: Score(buf[p..p + SCORE_SIZE].try_into().unwrap()),
+            blocksize: u16get(&buf[p + SCORE_SIZE..]),
+            prev: Score(
+                buf[p + SCORE_SIZE + 2..p + 2 * SCORE_SIZE + 2]
+                    .try_into()
+                    .unwrap(),
+            ),
+        })
+    }
+}
+
+/// scores waiting for their pointer blocks, level by level
+struct Tree {
+    base: u8,
+    fanout: usize,
+    levels: Vec<Vec<Score>>,
+}
+
+impl Tree {
+    /// add the score of a block at `level`, storing pointer blocks that fill up
+    fn push<S: WriteStore>(
+        &mut self,
+        store: &mut S,
+        level: usize,
+        score: Score,
+    ) -> Result<(), String> {
+        if level == self.levels.len() {
+            if level == DEPTH_MASK as usize + 1 {
+                return Err(String::from("tree too deep"));
+            }
+            self.levels.push(Vec::with_capacity(self.fanout));
+        }
+        self.levels[level].push(score);
+        if self.levels[level].len() == self.fanout {
+            self.flush(store, level)?;
+        }
+        Ok(())
+    }
+
+    /// store the pointer block of the scores at `level`
+    fn flush<S: WriteStore>(&mut self, store: &mut S, level: usize) -> Result<(), String> {
+        let kind = self.base + level as u8 + 1;
+        let p: Vec<u8> = self.levels[level].drain(..).flat_map(|s| s.0).collect();
+        let score = store.write(kind, zero_truncate(kind, &p))?;
+        self.push(store, level + 1, score)
+    }
+
+    /// store the partial pointer blocks and return the depth and top score
+    fn finish<S: WriteStore>(&mut self, store: &mut S) -> Result<(u8, Score), String> {
+        let mut level = 0;
+        while level < self.levels.len() {
+            let last = level + 1 == self.levels.len();
+            if last && self.levels[level].len() == 1 {
+                return Ok((level as u8, self.levels[level][0]));
+            }
+            if !self.levels[level].is_empty() {
+                self.flush(store, level)?;
+            }
+            level += 1;
+        }
+        Ok((0, Score::ZERO))
+    }
+}
+
+/// read until `buf` is full or the input ends
+fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> Result<usize, String> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(format!("read: {e}")),
+        }
+    }
+    Ok(n)
+}
+
+/// fetch a block and pad it back to `size` bytes
+fn fetch<S: BlockStore>(
+    store: &mut S,
+    score: &Score,
+    kind: u8,
+    size: usize,
+) -> Result<Vec<u8>, String> {
+    let mut data = if *score == Score::ZERO {
+        Vec::new()
+    } else {
+        store
+            .get(score, kind)?
+            .ok_or(format!("missing block {score}/{kind}"))?
+    };
+    if data.len() > size {
+        return Err(format!("block {score} larger than {size} bytes"));
+    }
+    zero_extend(kind, &mut data, size);
+    Ok(data)
+}
@@ -0,0 +1,366 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Read-only fossil file system images
+//!
+//! Fossil keeps the active file system in local blocks of a disk
+//! image and archives snapshots to venti. The image starts with a
+//! header at [`HEADER_OFFSET`] naming the block numbers of the
+//! superblock, the label blocks and the data blocks. Pointers to
+//! local blocks are local scores; once a block has been archived,
+//! pointers to it are venti scores, resolved through a block store.
+//!
+//! The file tree below the active root has the layout of a vac
+//! archive, so a [`Fossil`] is read through [`Vac`].
+use std::io::{Read, Seek};
+
+use super::block::{DIR_TYPE, zero_truncate};
+use super::entry::{ENTRY_SIZE, Entry, Root};
+use super::score::{SCORE_SIZE, Score};
+use super::store::BlockStore;
+use super::vac::Vac;
+use super::{nameget, nameput, read_at, u16get, u16put, u32get, u32put, u64get, u64put};
+
+/// offset of the header in the image
+pub const HEADER_OFFSET: u64 = 128 * 1024;
+pub const HEADER_MAGIC: u32 = 0x3776_ae89;
+pub const HEADER_VERSION: u16 = 1;
+/// size of a packed [`Header`]
+pub const HEADER_SIZE: usize = 24;
+
+pub const SUPER_MAGIC: u32 = 0x2340_a3b1;
+pub const SUPER_VERSION: u16 = 1;
+/// size of a packed [`Super`]
+pub const SUPER_SIZE: usize = 182;
+/// size of the file system name in the superblock
+pub const SUPER_NAME_SIZE: usize = 128;
+
+/// size of a packed [`Label`]
+pub const LABEL_SIZE: usize = 14;
+/// label state of an unused block
+pub const BLOCK_FREE: u8 = 0;
+/// label state of a block in use
+pub const BLOCK_ALLOC: u8 = 1;
+/// label state of a block copied for a snapshot
+pub const BLOCK_COPIED: u8 = 2;
+/// label state of a block stored in venti
+pub const BLOCK_VENTI: u8 = 4;
+/// label state of a block no longer referenced by the active tree
+pub const BLOCK_CLOSED: u8 = 8;
+/// label state of an unusable block
+pub const BLOCK_BAD: u8 = 0xff;
+
+/// Image header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub blocksize: u16,
+    /// block number of the superblock
+    pub sup: u32,
+    /// block number of the first label block
+    pub label: u32,
+    /// block number of the first data block
+    pub data: u32,
+    /// block number past the last data block
+    pub end: u32,
+}
+
+/// Superblock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Super {
+    /// oldest epoch still in the image
+    pub epoch_low: u32,
+    /// current epoch
+    pub epoch_high: u32,
+    /// next qid to hand out
+    pub qid: u64,
+    /// data block of the active root
+    pub active: u32,
+    /// data block of the root being archived
+    pub next: u32,
+    /// data block of the root of the current snapshot
+    pub current: u32,
+    /// root of the last archived snapshot, or the zero score
+    pub last: Score,
+    pub name: String,
+}
+
+/// Label of a data block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub state: u8,
+    /// block type, as the venti type of the block
+    pub kind: u8,
+    /// epoch the block was allocated in
+    pub epoch: u32,
+    /// epoch the block was closed in
+    pub epoch_close: u32,
+    pub tag: u32,
+}
+
+/// Fossil image with a block store for its archived blocks
+///
+/// Serves local blocks from the image and other blocks from the
+/// store, so the trees of the image can be walked with [`Entry`]
+/// and [`Vac`].
+pub struct Fossil<R, S> {
+    r: R,
+    pub header: Header,
+    pub sup: Super,
+    venti: S,
+}
+
+impl Header {
+    /// Pack into the first [`HEADER_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        u32put(buf, HEADER_MAGIC);
+        u16put(&mut buf[4..], HEADER_VERSION);
+        u16put(&mut buf[6..], self.blocksize);
+        u32put(&mut buf[8..], self.sup);
+        u32put(&mut buf[12..], self.label);
+        u32put(&mut buf[16..], self.data);
+        u32put(&mut buf[20..], self.end);
+    }
+
+    /// Unpack from the first [`HEADER_SIZE`] bytes of `buf`
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is short, the magic or version is wrong, or the
+    /// layout is inconsistent
+    pub fn unpack(buf: &[u8]) -> Result<Header, String> {
+        if buf.len() < HEADER_SIZE {
+            return Err(format!("header of {} bytes", buf.len()));
+        }
+        let magic = u32get(buf);
+        if magic != HEADER_MAGIC {
+            return Err(format!("bad header magic {magic:#010x}"));
+        }
+        let version = u16get(&buf[4..]);
+        if version != HEADER_VERSION {
+            return Err(format!("unsupported header version {version}"));
+        }
+        let h = Header {
+            blocksize: u16get(&buf[6..]),
+            sup: u32get(&buf[8..]),
+            label: u32get(&buf[12..]),
+            data: u32get(&buf[16..]),
+            end: u32get(&buf[20..]),
+        };
+        if (h.blocksize as usize) < SUPER_SIZE.max(LABEL_SIZE).max(3 * ENTRY_SIZE) {
+            return Err(format!("bad block size {}", h.blocksize));
+        }
+        if h.sup >= h.label || h.label >= h.data || h.data > h.end {
+            return Err(format!(
+                "bad layout super {} label {} data {} end {}",
+                h.sup, h.label, h.data, h.end
+            ));
+        }
+        Ok(h)
+    }
+}
+
+impl Super {
+    /// Pack into the first [`SUPER_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        u32put(buf, SUPER_MAGIC);
+        u16put(&mut buf[4..], SUPER_VERSION);
+        u32put(&mut buf[6..], self.epoch_low);
+        u32put(&mut buf[10..], self.epoch_high);
+        u64put(&mut buf[14..], self.qid);
+        u32put(&mut buf[22..], self.active);
+        u32put(&mut buf[26..], self.next);
+        u32put(&mut buf[30..], self.current);
+        buf[34..34 + SCORE_SIZE].copy_from_slice(&self.last.0);
+        nameput(&mut buf[54..54 + SUPER_NAME_SIZE], &self.name);
+    }
+
+    /// Unpack from the first [`SUPER_SIZE`] bytes of `buf`
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is short or the magic or version is wrong
+    pub fn unpack(buf: &[u8]) -> Result<Super, String> {
+        if buf.len() < SUPER_SIZE {
+            return Err(format!("superblock of {} bytes", buf.len()));
+        }
+        let magic = u32get(buf);
+        if magic != SUPER_MAGIC {
+            return Err(format!("bad superblock magic {magic:#010x}"));
+        }
+        let version = u16get(&buf[4..]);
+        if version != SUPER_VERSION {
+            return Err(format!("unsupported superblock version {version}"));
+        }
+        Ok(Super {
+            epoch_low: u32get(&buf[6..]),
+            epoch_high: u32get(&buf[10..]),
+            qid: u64get(&buf[14..]),
+            active: u32get(&buf[22..]),
+            next: u32get(&buf[26..]),
+            current: u32get(&buf[30..]),
+            last: Score(buf[34..34 + SCORE_SIZE].try_into().unwrap()),
+            name: nameget(&buf[54..54 + SUPER_NAME_SIZE]),
+        })
+    }
+}
+
+impl Label {
+    /// Pack into the first [`LABEL_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        buf[0] = self.state;
+        buf[1] = self.kind;
+        u32put(&mut buf[2..], self.epoch);
+        u32put(&mut buf[6..], self.epoch_close);
+        u32put(&mut buf[10..], self.tag);
+    }
+
+    /// Unpack from the first [`LABEL_SIZE`] bytes of `buf`
+    pub fn unpack(buf: &[u8]) -> Label {
+        Label {
+            state: buf[0],
+            kind: buf[1],
+            epoch: u32get(&buf[2..]),
+            epoch_close: u32get(&buf[6..]),
+            tag: u32get(&buf[10..]),
+        }
+    }
+}
+
+impl<R: Read + Seek, S: BlockStore> Fossil<R, S> {
+    /// Read the header and superblock of the image in `r`
+    ///
+    /// `venti` holds the blocks archived from the image.
+    ///
+    /// # Errors
+    ///
+    /// If the header or superblock cannot be read or is damaged
+    pub fn open(mut r: R, venti: S) -> Result<Fossil<R, S>, String> {
+        let mut buf = [0u8; HEADER_SIZE];
+        read_at(&mut r, HEADER_OFFSET, &mut buf)?;
+        let header = Header::unpack(&buf)?;
+        let mut buf = [0u8; SUPER_SIZE];
+        read_at(
+            &mut r,
+            header.sup as u64 * header.blocksize as u64,
+            &mut buf,
+        )?;
+        let sup = Super::unpack(&buf)?;
+        Ok(Fossil {
+            r,
+            header,
+            sup,
+            venti,
+        })
+    }
+
+    /// Label of data block `addr`
+    ///
+    /// # Errors
+    ///
+    /// If `addr` is outside the image or the label cannot be read
+    pub fn label(&mut self, addr: u32) -> Result<Label, String> {
+        let h = &self.header;
+        if addr >= h.end - h.data {
+            return Err(format!("block {addr} outside the image"));
+        }
+        let per_block = (h.blocksize as usize / LABEL_SIZE) as u64;
+        let off = (h.label as u64 + addr as u64 / per_block) * h.blocksize as u64
+            + (addr as u64 % per_block) * LABEL_SIZE as u64;
+        if off + LABEL_SIZE as u64 > h.data as u64 * h.blocksize as u64 {
+            return Err(format!("label of block {addr} outside the label blocks"));
+        }
+        let mut buf = [0u8; LABEL_SIZE];
+        read_at(&mut self.r, off, &mut buf)?;
+        Ok(Label::unpack(&buf))
+    }
+
+    /// Read data block `addr` in full
+    ///
+    /// # Errors
+    ///
+    /// If `addr` is outside the image or the block cannot be read
+    pub fn read_block(&mut self, addr: u32) -> Result<Vec<u8>, String> {
+        let h = &self.header;
+        if addr >= h.end - h.data {
+            return Err(format!("block {addr} outside the image"));
+        }
+        let off = (h.data as u64 + addr as u64) * h.blocksize as u64;
+        let mut buf = vec![0u8; h.blocksize as usize];
+        read_at(&mut self.r, off, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Entry of the root source in data block `addr`
+    ///
+    /// # Errors
+    ///
+    /// If the block is not a directory block in use
+    pub fn root_entry(&mut self, addr: u32) -> Result<Entry, String> {
+        let label = self.label(addr)?;
+        if label.kind != DIR_TYPE || matches!(label.state, BLOCK_FREE | BLOCK_BAD) {
+            return Err(format!(
+                "root block {addr} of type {} in state {:#x}",
+                label.kind, label.state
+            ));
+        }
+        let buf = self.read_block(addr)?;
+        Ok(Entry::unpack(&buf))
+    }
+
+    /// Open the active file system
+    ///
+    /// # Errors
+    ///
+    /// If the active root or its top directory cannot be read
+    pub fn active(mut self) -> Result<Vac<Fossil<R, S>>, String> {
+        let top = self.root_entry(self.sup.active)?;
+        let root = Root {
+            name: self.sup.name.clone(),
+            kind: String::from("fossil"),
+            score: top.score,
+            blocksize: self.header.blocksize,
+            prev: self.sup.last,
+        };
+        Vac::with_top(self, root, &top)
+    }
+
+    /// Open the last archived snapshot
+    ///
+    /// # Errors
+    ///
+    /// If nothing has been archived or the snapshot cannot be read
+    pub fn archive(self) -> Result<Vac<Fossil<R, S>>, String> {
+        if self.sup.last == Score::ZERO {
+            return Err(String::from("no archived snapshot"));
+        }
+        let last = self.sup.last;
+        Vac::open(self, &last)
+    }
+
+    /// The image and the block store
+    pub fn into_inner(self) -> (R, S) {
+        (self.r, self.venti)
+    }
+}
+
+impl<R: Read + Seek, S: BlockStore> BlockStore for Fossil<R, S> {
+    /// Local blocks come without their trailing zeros, like venti
+    /// blocks, and must be labelled with `kind`.
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String> {
+        let Some(addr) = score.local_addr() else {
+            return self.venti.get(score, kind);
+        };
+        let label = self.label(addr)?;
+        if matches!(label.state, BLOCK_FREE | BLOCK_BAD) {
+            return Err(format!("block {addr} is not in use"));
+        }
+        if label.kind != kind {
+            return Err(format!(
+                "block {addr} has type {}, expected {kind}",
+                label.kind
+            ));
+        }
+        let mut data = self.read_block(addr)?;
+        let n = zero_truncate(kind, &data).len();
+        data.truncate(n);
+        Ok(Some(data))
+    }
+}
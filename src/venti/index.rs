@@ -0,0 +1,360 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti index sections
+//!
+//! The index maps a score to the address of its clump. Scores are
+//! hashed into buckets spread over one or more index sections; each
+//! bucket is a block holding a sorted list of entries. Addresses are
+//! global across all arenas, and the arena map in the index sections
+//! says which range of addresses belongs to which arena.
+use std::io::{Read, Seek};
+
+use super::arena::{
+    AMap, ANAME_SIZE, Arena, HEAD_SIZE, MAX_AMAP_SIZE, PART_BLANK, Partition, parse_amap,
+};
+use super::block::{from_disk_type, same_type, to_disk_type};
+use super::clump::CLUMP_SIZE;
+use super::score::{SCORE_SIZE, Score};
+use super::{nameget, nameput, read_at, u16get, u16put, u32get, u32put, u64get, u64put};
+
+pub const ISECT_MAGIC: u32 = 0xd15c5ec7;
+pub const ISECT_VERSION1: u32 = 1;
+/// adds the block base and bucket magic
+pub const ISECT_VERSION2: u32 = 2;
+pub const ISECT_SIZE1: usize = 7 * 4 + 2 * ANAME_SIZE;
+pub const ISECT_SIZE2: usize = ISECT_SIZE1 + 4;
+/// bucket header: entry count and magic
+pub const IBUCKET_SIZE: usize = 2 + 4;
+pub const IENTRY_SIZE: usize = 8 + 4 + 2 * 2 + 2 + SCORE_SIZE;
+/// clump sizes are counted in blocks of this many bits
+pub const ABLOCK_LOG: u32 = 9;
+
+/// Index section header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ISect {
+    pub version: u32,
+    /// name of the section
+    pub name: String,
+    /// name of the index it belongs to
+    pub index: String,
+    pub blocksize: u32,
+    /// offset of the first bucket
+    pub blockbase: u32,
+    /// number of buckets in the section
+    pub blocks: u32,
+    /// first bucket number held
+    pub start: u32,
+    /// first bucket number not held
+    pub stop: u32,
+    /// magic of valid buckets; stale ones are treated as empty.
+    /// Version 1 sections have none and keep 0, which matches any bucket.
+    pub bucketmagic: u32,
+}
+
+/// Where a clump lives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IAddr {
+    /// global address of the clump
+    pub addr: u64,
+    /// uncompressed size of the block
+    pub size: u16,
+    /// block type
+    pub kind: u8,
+    /// clump size in units of `1 << ABLOCK_LOG` bytes
+    pub blocks: u8,
+}
+
+/// Index entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IEntry {
+    pub score: Score,
+    /// time the entry was written
+    pub wtime: u32,
+    pub train: u16,
+    pub ia: IAddr,
+}
+
+impl ISect {
+    /// Pack into the start of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        u32put(buf, ISECT_MAGIC);
+        u32put(&mut buf[4..], self.version);
+        nameput(&mut buf[8..8 + ANAME_SIZE], &self.name);
+        nameput(&mut buf[8 + ANAME_SIZE..8 + 2 * ANAME_SIZE], &self.index);
+        let p = 8 + 2 * ANAME_SIZE;
+        u32put(&mut buf[p..], self.blocksize);
+        u32put(&mut buf[p + 4..], self.blockbase);
+        u32put(&mut buf[p + 8..], self.blocks);
+        u32put(&mut buf[p + 12..], self.start);
+        u32put(&mut buf[p + 16..], self.stop);
+        if self.version == ISECT_VERSION2 {
+            u32put(&mut buf[p + 20..], self.bucketmagic);
+        }
+    }
+
+    /// Unpack from the start of `buf`
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is short, the magic is wrong or the version is not supported
+    pub fn unpack(buf: &[u8]) -> Result<ISect, String> {
+        if buf.len() < ISECT_SIZE1 {
+            return Err(format!("index section header of {} bytes", buf.len()));
+        }
+        let magic = u32get(buf);
+        if magic != ISECT_MAGIC {
+            return Err(format!("bad index section magic {magic:#x}"));
+        }
+        let version = u32get(&buf[4..]);
+        if version != ISECT_VERSION1 && version != ISECT_VERSION2 {
+            return Err(format!("unsupported index section version {version}"));
+        }
+        if version == ISECT_VERSION2 && buf.len() < ISECT_SIZE2 {
+            return Err(format!("index section header of {} bytes", buf.len()));
+        }
+        let p = 8 + 2 * ANAME_SIZE;
+        let is = ISect {
+            version,
+            name: nameget(&buf[8..8 + ANAME_SIZE]),
+            index: nameget(&buf[8 + ANAME_SIZE..8 + 2 * ANAME_SIZE]),
+            blocksize: u32get(&buf[p..]),
+            blockbase: u32get(&buf[p + 4..]),
+            blocks: u32get(&buf[p + 8..]),
+            start: u32get(&buf[p + 12..]),
+            stop: u32get(&buf[p + 16..]),
+            bucketmagic: if version == ISECT_VERSION2 {
+                u32get(&buf[p + 20..])
+            } else {
+                0
+            },
+        };
+        if is.blocksize == 0 || !is.blocksize.is_power_of_two() || is.start > is.stop {
+            return Err(format!("bad index section {}", is.name));
+        }
+        Ok(is)
+    }
+
+    /// Offset of the arena map
+    pub fn tabbase(&self) -> u64 {
+        let blocksize = self.blocksize as u64;
+        (PART_BLANK + HEAD_SIZE + blocksize - 1) & !(blocksize - 1)
+    }
+
+    /// Space reserved for the arena map
+    pub fn tabsize(&self) -> u64 {
+        (self.blockbase as u64).saturating_sub(self.tabbase())
+    }
+
+    /// Offset of bucket number `bucket`, which must be in the section
+    pub fn bucket_offset(&self, bucket: u32) -> u64 {
+        self.blockbase as u64 + (bucket - self.start) as u64 * self.blocksize as u64
+    }
+}
+
+impl IEntry {
+    /// Pack into the first [`IENTRY_SIZE`] bytes of `buf`
+    pub fn pack(&self, buf: &mut [u8]) {
+        buf[..SCORE_SIZE].copy_from_slice(&self.score.0);
+        u32put(&mut buf[20..], self.wtime);
+        u16put(&mut buf[24..], self.train);
+        u64put(&mut buf[26..], self.ia.addr);
+        u16put(&mut buf[34..], self.ia.size);
+        buf[36] = to_disk_type(self.ia.kind);
+        buf[37] = self.ia.blocks;
+    }
+
+    /// Unpack from the first [`IENTRY_SIZE`] bytes of `buf`
+    pub fn unpack(buf: &[u8]) -> IEntry {
+        IEntry {
+            score: Score(buf[..SCORE_SIZE].try_into().unwrap()),
+            wtime: u32get(&buf[20..]),
+            train: u16get(&buf[24..]),
+            ia: IAddr {
+                addr: u64get(&buf[26..]),
+                size: u16get(&buf[34..]),
+                kind: from_disk_type(buf[36]),
+                blocks: buf[37],
+            },
+        }
+    }
+}
+
+impl IAddr {
+    /// Number of `1 << ABLOCK_LOG` byte units a clump of `size` stored bytes covers
+    pub fn blocks_for(size: usize) -> u8 {
+        ((CLUMP_SIZE + size).div_ceil(1 << ABLOCK_LOG)) as u8
+    }
+}
+
+/// Pack a bucket holding `entries`, which must be sorted by score
+///
+/// # Errors
+///
+/// If the entries don't fit in `buf`
+pub fn pack_bucket(entries: &[IEntry], magic: u32, buf: &mut [u8]) -> Result<(), String> {
+    if IBUCKET_SIZE + entries.len() * IENTRY_SIZE > buf.len() {
+        return Err(format!("{} entries overflow bucket", entries.len()));
+    }
+    u16put(buf, entries.len() as u16);
+    u32put(&mut buf[2..], magic);
+    for (i, e) in entries.iter().enumerate() {
+        e.pack(&mut buf[IBUCKET_SIZE + i * IENTRY_SIZE..]);
+    }
+    Ok(())
+}
+
+/// Unpack the entries of a bucket
+///
+/// A bucket whose magic differs from `magic` is empty, unless
+/// `magic` is 0.
+///
+/// # Errors
+///
+/// If `buf` is short or the entry count doesn't fit the bucket
+pub fn unpack_bucket(buf: &[u8], magic: u32) -> Result<Vec<IEntry>, String> {
+    if buf.len() < IBUCKET_SIZE {
+        return Err(format!("bucket of {} bytes", buf.len()));
+    }
+    if magic != 0 && u32get(&buf[2..]) != magic {
+        return Ok(Vec::new());
+    }
+    let n = u16get(buf) as usize;
+    if IBUCKET_SIZE + n * IENTRY_SIZE > buf.len() {
+        return Err(format!("bucket claims {n} entries"));
+    }
+    Ok((0..n)
+        .map(|i| IEntry::unpack(&buf[IBUCKET_SIZE + i * IENTRY_SIZE..]))
+        .collect())
+}
+
+/// Read-only view of a venti index
+pub struct Index<R> {
+    /// sections, ordered by bucket number
+    sects: Vec<(ISect, R)>,
+    /// arena map of the global address space
+    pub amap: Vec<AMap>,
+    /// total number of buckets
+    pub buckets: u32,
+    /// divisor from score hash to bucket number
+    div: u32,
+}
+
+impl<R: Read + Seek> Index<R> {
+    /// Read the section headers and the arena map
+    ///
+    /// The sections together must cover all buckets.
+    ///
+    /// # Errors
+    ///
+    /// If a header cannot be read, or the sections don't fit together
+    pub fn open(sections: Vec<R>) -> Result<Index<R>, String> {
+        let mut sects = Vec::with_capacity(sections.len());
+        for mut r in sections {
+            let mut buf = [0u8; ISECT_SIZE2];
+            read_at(&mut r, PART_BLANK, &mut buf)?;
+            sects.push((ISect::unpack(&buf)?, r));
+        }
+        sects.sort_by_key(|(is, _)| is.start);
+        let mut buckets = 0;
+        for (is, _) in &sects {
+            if is.start != buckets || is.stop - is.start > is.blocks {
+                return Err(format!("index section {} out of place", is.name));
+            }
+            buckets = is.stop;
+        }
+        let Some((is, r)) = sects.first_mut() else {
+            return Err(String::from("no index sections"));
+        };
+        if buckets == 0 {
+            return Err(String::from("index has no buckets"));
+        }
+        if is.tabsize() > MAX_AMAP_SIZE {
+            return Err(format!(
+                "arena map of index section {} is {} bytes",
+                is.name,
+                is.tabsize()
+            ));
+        }
+        let mut tab = vec![0u8; is.tabsize() as usize];
+        read_at(r, is.tabbase(), &mut tab)?;
+        let amap = parse_amap(&tab)?;
+        let div = ((1u64 << 32).div_ceil(buckets as u64)) as u32;
+        Ok(Index {
+            sects,
+            amap,
+            buckets,
+            div,
+        })
+    }
+
+    /// Bucket number `score` hashes to
+    pub fn bucket(&self, score: &Score) -> u32 {
+        u32get(&score.0) / self.div
+    }
+
+    /// Find the clump address of `score`
+    ///
+    /// # Errors
+    ///
+    /// If the bucket cannot be read
+    pub fn lookup(&mut self, score: &Score) -> Result<Option<IAddr>, String> {
+        let bucket = self.bucket(score);
+        let (is, r) = self
+            .sects
+            .iter_mut()
+            .find(|(is, _)| is.start <= bucket && bucket < is.stop)
+            .ok_or(format!("no index section for bucket {bucket}"))?;
+        let mut buf = vec![0u8; is.blocksize as usize];
+        read_at(r, is.bucket_offset(bucket), &mut buf)?;
+        let entries = unpack_bucket(&buf, is.bucketmagic)?;
+        Ok(entries
+            .binary_search_by(|e| e.score.cmp(score))
+            .ok()
+            .map(|i| entries[i].ia.clone()))
+    }
+}
+
+/// Fetch a block by score from an index and its arena partitions
+///
+/// Looks up the clump address in `index`, finds the arena holding it
+/// in `parts` and decodes the clump, checking its score and type.
+///
+/// # Errors
+///
+/// If the index or clump cannot be read, the arena is missing or the
+/// clump is not the one the index promised
+pub fn read_block<R: Read + Seek, P: Read + Seek>(
+    index: &mut Index<R>,
+    parts: &mut [Partition<P>],
+    score: &Score,
+) -> Result<Option<(u8, Vec<u8>)>, String> {
+    let Some(ia) = index.lookup(score)? else {
+        return Ok(None);
+    };
+    let m = index
+        .amap
+        .iter()
+        .find(|m| m.start <= ia.addr && ia.addr < m.stop)
+        .ok_or(format!("no arena for address {}", ia.addr))?;
+    let (arena, part) = find_arena(parts, &m.name)?;
+    let (clump, data) = part.read_clump(&arena, ia.addr - m.start)?;
+    if clump.info.score != *score || !same_type(clump.info.kind, ia.kind) {
+        return Err(format!("clump at {} is not {score}", ia.addr));
+    }
+    if Score::of(&data) != *score {
+        return Err(format!("block {score} does not match its score"));
+    }
+    Ok(Some((ia.kind, data)))
+}
+
+/// locate the arena called `name` in one of `parts`
+fn find_arena<'a, P: Read + Seek>(
+    parts: &'a mut [Partition<P>],
+    name: &str,
+) -> Result<(Arena, &'a mut Partition<P>), String> {
+    for part in parts.iter_mut() {
+        if let Some(n) = part.map.iter().position(|m| m.name == name) {
+            return Ok((part.arena(n)?, part));
+        }
+    }
+    Err(format!("arena {name} not found"))
+}
@@ -0,0 +1,64 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti structures built around whack compressed blocks
+//!
+//! Venti stores each block as a clump, compressed with whack when
+//! that makes it smaller. All on-disk integers are big-endian.
+use std::io::{Read, Seek, SeekFrom};
+
+pub mod arena;
+pub mod block;
+pub mod client;
+pub mod clump;
+pub mod entry;
+pub mod fossil;
+pub mod index;
+pub mod proto;
+pub mod score;
+pub mod server;
+pub mod store;
+pub mod vac;
+pub mod vacfs;
+
+pub(crate) fn u16get(p: &[u8]) -> u16 {
+    u16::from_be_bytes([p[0], p[1]])
+}
+
+pub(crate) fn u32get(p: &[u8]) -> u32 {
+    u32::from_be_bytes(p[..4].try_into().unwrap())
+}
+
+pub(crate) fn u16put(p: &mut [u8], v: u16) {
+    p[..2].copy_from_slice(&v.to_be_bytes());
+}
+
+pub(crate) fn u32put(p: &mut [u8], v: u32) {
+    p[..4].copy_from_slice(&v.to_be_bytes());
+}
+
+pub(crate) fn u64get(p: &[u8]) -> u64 {
+    u64::from_be_bytes(p[..8].try_into().unwrap())
+}
+
+pub(crate) fn u64put(p: &mut [u8], v: u64) {
+    p[..8].copy_from_slice(&v.to_be_bytes());
+}
+
+/// copy a name into a fixed size, NUL padded field
+pub(crate) fn nameput(p: &mut [u8], name: &str) {
+    let n = name.len().min(p.len() - 1);
+    p[..n].copy_from_slice(&name.as_bytes()[..n]);
+    p[n..].fill(0);
+}
+
+/// read a name from a fixed size, NUL padded field
+pub(crate) fn nameget(p: &[u8]) -> String {
+    let n = p.iter().position(|&c| c == 0).unwrap_or(p.len());
+    String::from_utf8_lossy(&p[..n]).into_owned()
+}
+
+/// fill `buf` from offset `off` of `r`
+pub(crate) fn read_at<R: Read + Seek>(r: &mut R, off: u64, buf: &mut [u8]) -> Result<(), String> {
+    r.seek(SeekFrom::Start(off))
+        .and_then(|_| r.read_exact(buf))
+        .map_err(|e| format!("read at {off}: {e}"))
+}
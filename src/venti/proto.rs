@@ -0,0 +1,346 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti wire protocol
+//!
+//! After both sides exchange a version line, every message travels as
+//! a two byte length followed by the message type, a tag and the
+//! fields of that type. Strings are a two byte length and the bytes.
+//! Block types travel as the old types of [`to_disk_type`] and are
+//! converted when messages are packed and unpacked.
+use std::io::{BufRead, Read, Write};
+
+use super::block::{from_disk_type, to_disk_type};
+use super::score::{SCORE_SIZE, Score};
+use super::{u16get, u16put};
+
+/// version line sent by both sides, without the newline
+pub const VERSION: &str = "venti-02-libventi";
+/// longest version line accepted
+pub const MAX_VERSION_LINE: usize = 256;
+/// largest message body the length field can describe
+pub const MAX_MSG_SIZE: usize = 0xffff;
+
+pub const VT_RERROR: u8 = 1;
+pub const VT_TPING: u8 = 2;
+pub const VT_RPING: u8 = 3;
+pub const VT_THELLO: u8 = 4;
+pub const VT_RHELLO: u8 = 5;
+pub const VT_TGOODBYE: u8 = 6;
+pub const VT_TREAD: u8 = 12;
+pub const VT_RREAD: u8 = 13;
+pub const VT_TWRITE: u8 = 14;
+pub const VT_RWRITE: u8 = 15;
+pub const VT_TSYNC: u8 = 16;
+pub const VT_RSYNC: u8 = 17;
+
+/// Message contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Msg {
+    /// reply to a failed request
+    Rerror(String),
+    Tping,
+    Rping,
+    Thello {
+        version: String,
+        uid: String,
+        strength: u8,
+        /// offered encryption methods
+        crypto: Vec<u8>,
+        /// offered compression methods
+        codec: Vec<u8>,
+    },
+    Rhello {
+        /// session id
+        sid: String,
+        /// chosen encryption method
+        rcrypto: u8,
+        /// chosen compression method
+        rcodec: u8,
+    },
+    /// end of session; has no reply
+    Tgoodbye,
+    Tread {
+        score: Score,
+        kind: u8,
+        /// most bytes wanted
+        count: u16,
+    },
+    Rread(Vec<u8>),
+    Twrite {
+        kind: u8,
+        data: Vec<u8>,
+    },
+    Rwrite(Score),
+    Tsync,
+    Rsync,
+}
+
+/// A message and the tag pairing a request with its reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fcall {
+    pub tag: u8,
+    pub msg: Msg,
+}
+
+impl Msg {
+    /// Message type byte
+    pub fn msgtype(&self) -> u8 {
+        match self {
+            Msg::Rerror(_) => VT_RERROR,
+            Msg::Tping => VT_TPING,
+            Msg::Rping => VT_RPING,
+            Msg::Thello { .. } => VT_THELLO,
+            Msg::Rhello { .. } => VT_RHELLO,
+            Msg::Tgoodbye => VT_TGOODBYE,
+            Msg::Tread { .. } => VT_TREAD,
+            Msg::Rread(_) => VT_RREAD,
+            Msg::Twrite { .. } => VT_TWRITE,
+            Msg::Rwrite(_) => VT_RWRITE,
+            Msg::Tsync => VT_TSYNC,
+            Msg::Rsync => VT_RSYNC,
+        }
+    }
+}
+
+impl Fcall {
+    /// Pack into a message body, without the length
+    ///
+    /// # Errors
+    ///
+    /// If a string, list or the whole body is too long for its length field
+    pub fn pack(&self) -> Result<Vec<u8>, String> {
+        let mut p = vec![self.msg.msgtype(), self.tag];
+        match &self.msg {
+            Msg::Rerror(e) => strput(&mut p, e)?,
+            Msg::Tping | Msg::Rping | Msg::Tgoodbye | Msg::Tsync | Msg::Rsync => {}
+            Msg::Thello {
+                version,
+                uid,
+                strength,
+                crypto,
+                codec,
+            } => {
+                strput(&mut p, version)?;
+                strput(&mut p, uid)?;
+                p.push(*strength);
+                listput(&mut p, crypto)?;
+                listput(&mut p, codec)?;
+            }
+            Msg::Rhello {
+                sid,
+                rcrypto,
+                rcodec,
+            } => {
+                strput(&mut p, sid)?;
+                p.push(*rcrypto);
+                p.push(*rcodec);
+            }
+            Msg::Tread { score, kind, count } => {
+                p.extend_from_slice(&score.0);
+                p.extend_from_slice(&[to_disk_type(*kind), 0]);
+                p.extend_from_slice(&count.to_be_bytes());
+            }
+            Msg::Rread(data) => p.extend_from_slice(data),
+            Msg::Twrite { kind, data } => {
+                p.extend_from_slice(&[to_disk_type(*kind), 0, 0, 0]);
+                p.extend_from_slice(data);
+            }
+            Msg::Rwrite(score) => p.extend_from_slice(&score.0),
+        }
+        if p.len() > MAX_MSG_SIZE {
+            return Err(format!("message of {} bytes too long", p.len()));
+        }
+        Ok(p)
+    }
+
+    /// Unpack a message body, without the length
+    ///
+    /// # Errors
+    ///
+    /// If the type is unknown or the fields don't fill the body exactly
+    pub fn unpack(buf: &[u8]) -> Result<Fcall, String> {
+        if buf.len() < 2 {
+            return Err(String::from("short message"));
+        }
+        let mut p = Fields { buf, pos: 2 };
+        let msg = match buf[0] {
+            VT_RERROR => Msg::Rerror(p.string()?),
+            VT_TPING => Msg::Tping,
+            VT_RPING => Msg::Rping,
+            VT_THELLO => Msg::Thello {
+                version: p.string()?,
+                uid: p.string()?,
+                strength: p.byte()?,
+                crypto: p.list()?,
+                codec: p.list()?,
+            },
+            VT_RHELLO => Msg::Rhello {
+                sid: p.string()?,
+                rcrypto: p.byte()?,
+                rcodec: p.byte()?,
+            },
+            VT_TGOODBYE => Msg::Tgoodbye,
+            VT_TREAD => {
+                let score = p.score()?;
+                let kind = from_disk_type(p.byte()?);
+                p.take(1)?;
+                Msg::Tread {
+                    score,
+                    kind,
+                    count: u16get(p.take(2)?),
+                }
+            }
+            VT_RREAD => Msg::Rread(p.rest().to_vec()),
+            VT_TWRITE => {
+                let kind = from_disk_type(p.byte()?);
+                p.take(3)?;
+                Msg::Twrite {
+                    kind,
+                    data: p.rest().to_vec(),
+                }
+            }
+            VT_RWRITE => Msg::Rwrite(p.score()?),
+            VT_TSYNC => Msg::Tsync,
+            VT_RSYNC => Msg::Rsync,
+            t => return Err(format!("unknown message type {t}")),
+        };
+        if p.pos != buf.len() {
+            return Err(format!(
+                "{} bytes left over in message type {}",
+                buf.len() - p.pos,
+                buf[0]
+            ));
+        }
+        Ok(Fcall { tag: buf[1], msg })
+    }
+}
+
+/// Send a message with its length
+///
+/// # Errors
+///
+/// If the message cannot be packed or written
+pub fn write_fcall<W: Write>(w: &mut W, f: &Fcall) -> Result<(), String> {
+    let body = f.pack()?;
+    let mut len = [0u8; 2];
+    u16put(&mut len, body.len() as u16);
+    w.write_all(&len)
+        .and_then(|_| w.write_all(&body))
+        .and_then(|_| w.flush())
+        .map_err(|e| format!("write message: {e}"))
+}
+
+/// Receive a message
+///
+/// Returns `None` if the connection ended cleanly before a message.
+///
+/// # Errors
+///
+/// If reading fails partway or the message is malformed
+pub fn read_fcall<R: Read>(r: &mut R) -> Result<Option<Fcall>, String> {
+    let mut len = [0u8; 2];
+    let n = r
+        .read(&mut len[..1])
+        .map_err(|e| format!("read message: {e}"))?;
+    if n == 0 {
+        return Ok(None);
+    }
+    r.read_exact(&mut len[1..])
+        .map_err(|e| format!("read message: {e}"))?;
+    let mut body = vec![0u8; u16get(&len) as usize];
+    r.read_exact(&mut body)
+        .map_err(|e| format!("read message: {e}"))?;
+    Fcall::unpack(&body).map(Some)
+}
+
+/// Send our version line
+///
+/// # Errors
+///
+/// If writing fails
+pub fn write_version<W: Write>(w: &mut W) -> Result<(), String> {
+    w.write_all(format!("{VERSION}\n").as_bytes())
+        .and_then(|_| w.flush())
+        .map_err(|e| format!("write version: {e}"))
+}
+
+/// Read the peer's version line and check it speaks venti-02
+///
+/// # Errors
+///
+/// If reading fails, the line is too long or names another version
+pub fn read_version<R: BufRead>(r: &mut R) -> Result<String, String> {
+    let mut line = Vec::new();
+    r.take(MAX_VERSION_LINE as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| format!("read version: {e}"))?;
+    if line.pop() != Some(b'\n') {
+        return Err(String::from("bad version line"));
+    }
+    let line = String::from_utf8_lossy(&line).into_owned();
+    if !line
+        .split('-')
+        .nth(1)
+        .is_some_and(|v| v.split(':').any(|v| v == "02"))
+    {
+        return Err(format!("unsupported version {line}"));
+    }
+    Ok(line)
+}
+
+fn strput(p: &mut Vec<u8>, s: &str) -> Result<(), String> {
+    if s.len() > u16::MAX as usize {
+        return Err(String::from("string too long"));
+    }
+    p.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    p.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn listput(p: &mut Vec<u8>, l: &[u8]) -> Result<(), String> {
+    if l.len() > u8::MAX as usize {
+        return Err(String::from("list too long"));
+    }
+    p.push(l.len() as u8);
+    p.extend_from_slice(l);
+    Ok(())
+}
+
+/// cursor over the fields of a message body
+struct Fields<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() - self.pos < n {
+            return Err(format!("message type {} too short", self.buf[0]));
+        }
+        self.pos += n;
+        Ok(&self.buf[self.pos - n..self.pos])
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.buf[self.pos..];
+        self.pos = self.buf.len();
+        rest
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn score(&mut self) -> Result<Score, String> {
+        Ok(Score(self.take(SCORE_SIZE)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let n = u16get(self.take(2)?) as usize;
+        Ok(String::from_utf8_lossy(self.take(n)?).into_owned())
+    }
+
+    fn list(&mut self) -> Result<Vec<u8>, String> {
+        let n = self.byte()? as usize;
+        Ok(self.take(n)?.to_vec())
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Venti scores
+//!
+//! Venti addresses every block by the SHA-1 of its contents.
+use std::fmt;
+use std::str::FromStr;
+
+use crate::sha1::sha1;
+use crate::unwhack::unwhack;
+
+/// size of a score in bytes
+pub const SCORE_SIZE: usize = 20;
+
+/// SHA-1 of a block, its address in venti
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Score(pub [u8; SCORE_SIZE]);
+
+impl Score {
+    /// Score of the empty block, venti's zero score
+    pub const ZERO: Score = Score([
+        0xda, 0x39, 0xa3, 0xee, 0x5e, 0x6b, 0x4b, 0x0d, 0x32, 0x55, 0xbf, 0xef, 0x95, 0x60, 0x18,
+        0x90, 0xaf, 0xd8, 0x07, 0x09,
+    ]);
+
+    /// Compute the score of `data`
+    pub fn of(data: &[u8]) -> Score {
+        Score(sha1(data))
+    }
+
+    /// Local score of fossil block `addr`: 16 zero bytes and the address
+    pub fn local(addr: u32) -> Score {
+        let mut score = [0u8; SCORE_SIZE];
+        score[16..].copy_from_slice(&addr.to_be_bytes());
+        Score(score)
+    }
+
+    /// Block address of a local score
+    pub fn local_addr(&self) -> Option<u32> {
+        if self.0[..16].iter().all(|&b| b == 0) {
+            Some(u32::from_be_bytes(self.0[16..].try_into().unwrap()))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Score({self})")
+    }
+}
+
+impl FromStr for Score {
+    type Err = String;
+
+    /// Parse 40 hex digits
+    fn from_str(s: &str) -> Result<Score, String> {
+        let digits = s.as_bytes();
+        if digits.len() != 2 * SCORE_SIZE || !digits.iter().all(u8::is_ascii_hexdigit) {
+            return Err(format!("bad score: {s}"));
+        }
+        let mut score = [0u8; SCORE_SIZE];
+        for (i, b) in score.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        Ok(Score(score))
+    }
+}
+
+/// Failure to read back a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadError {
+    /// the stored data could not be decoded
+    Decode(String),
+    /// the decoded data does not match its score
+    Integrity { expected: Score, actual: Score },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Decode(e) => write!(f, "{e}"),
+            ReadError::Integrity { expected, actual } => {
+                write!(f, "score mismatch: expected {expected}, got {actual}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Uncompress a block and check it against its score
+///
+/// Takes whack compressed data in `src`, uncompresses it to at most
+/// `ndst` bytes with [`unwhack`] and verifies that the result hashes
+/// to `expected`.
+///
+/// # Errors
+///
+/// [`ReadError::Decode`] if the stream cannot be decompressed, and
+/// [`ReadError::Integrity`] if the data does not match the score
+pub fn unwhack_verified(src: &[u8], ndst: usize, expected: &Score) -> Result<Vec<u8>, ReadError> {
+    let data = unwhack(src, ndst).map_err(ReadError::Decode)?;
+    let actual = Score::of(&data);
+    if actual != *expected {
+        return Err(ReadError::Integrity {
+            expected: *expected,
+            actual,
+        });
+    }
+    Ok(data)
+}
@@ -0,0 +1,105 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Local venti server
+//!
+//! Answers the venti protocol on any stream, keeping the blocks in a
+//! [`WriteStore`]. A [`WhackStore`](super::store::WhackStore) around
+//! a plain store keeps them whack compressed and checks the score of
+//! every block read back.
+use std::io::{BufReader, Read, Write};
+use std::net::TcpListener;
+
+use super::clump::MAX_LUMP_SIZE;
+use super::proto::{Fcall, Msg, read_fcall, read_version, write_fcall, write_version};
+use super::score::Score;
+use super::store::WriteStore;
+
+/// Serve one connection until the client says goodbye or hangs up
+///
+/// Exchanges versions, expects a hello and then answers requests
+/// from `store`. Failed requests are answered with an error message
+/// and the session goes on.
+///
+/// # Errors
+///
+/// If the connection fails or the client breaks the protocol
+pub fn serve<S: WriteStore, C: Read + Write>(store: &mut S, conn: C) -> Result<(), String> {
+    let mut conn = BufReader::new(conn);
+    write_version(conn.get_mut())?;
+    read_version(&mut conn)?;
+    match read_fcall(&mut conn)? {
+        Some(Fcall {
+            tag,
+            msg: Msg::Thello { .. },
+        }) => {
+            let msg = Msg::Rhello {
+                sid: String::from("anonymous"),
+                rcrypto: 0,
+                rcodec: 0,
+            };
+            write_fcall(conn.get_mut(), &Fcall { tag, msg })?;
+        }
+        Some(f) => return Err(format!("expected hello, got {f:?}")),
+        None => return Ok(()),
+    }
+    while let Some(Fcall { tag, msg }) = read_fcall(&mut conn)? {
+        let msg = match msg {
+            Msg::Tgoodbye => break,
+            msg => answer(store, msg).unwrap_or_else(Msg::Rerror),
+        };
+        write_fcall(conn.get_mut(), &Fcall { tag, msg })?;
+    }
+    Ok(())
+}
+
+/// Serve the connections arriving on `listener`, one at a time
+///
+/// The error that ends a connection, if any, is handed to `report`
+/// with the address of the client.
+///
+/// # Errors
+///
+/// If accepting fails; errors of single connections end only that
+/// connection
+pub fn serve_listener<S: WriteStore, F: FnMut(String)>(
+    store: &mut S,
+    listener: &TcpListener,
+    mut report: F,
+) -> Result<(), String> {
+    for conn in listener.incoming() {
+        let conn = conn.map_err(|e| format!("accept: {e}"))?;
+        let peer = conn
+            .peer_addr()
+            .map_or_else(|_| String::from("unknown client"), |a| a.to_string());
+        if let Err(e) = serve(store, conn) {
+            report(format!("{peer}: {e}"));
+        }
+    }
+    Ok(())
+}
+
+/// handle a single request
+fn answer<S: WriteStore>(store: &mut S, msg: Msg) -> Result<Msg, String> {
+    match msg {
+        Msg::Tping => Ok(Msg::Rping),
+        Msg::Tread { score, kind, count } => {
+            if score == Score::ZERO {
+                return Ok(Msg::Rread(Vec::new()));
+            }
+            let data = store
+                .get(&score, kind)?
+                .ok_or(format!("no block with score {score}/{kind} exists"))?;
+            if data.len() > count as usize {
+                return Err(format!("block {score} larger than {count} bytes"));
+            }
+            Ok(Msg::Rread(data))
+        }
+        Msg::Twrite { kind, data } => {
+            if data.len() > MAX_LUMP_SIZE {
+                return Err(format!("block too large: {} bytes", data.len()));
+            }
+            store.write(kind, &data).map(Msg::Rwrite)
+        }
+        Msg::Tsync => store.sync().map(|_| Msg::Rsync),
+        msg => Err(format!("unexpected message type {}", msg.msgtype())),
+    }
+}
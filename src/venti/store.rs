@@ -0,0 +1,486 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Block stores
+//!
+//! A [`BlockStore`] hands out blocks by score and type, and a
+//! [`WriteStore`] takes new ones as well. The plain stores
+//! here keep the bytes they are given; wrapping one in a
+//! [`WhackStore`] makes it keep each block as a clump, whack
+//! compressed when that helps, and check every block it reads back
+//! against its score. An arena partition can be read as a store too,
+//! though not written: it is a [`BlockStore`] and not a [`WriteStore`].
+//!
+//! Every store keeps a block by its score and the old type it would
+//! have on disk, so types that [`same_type`] equates find the same
+//! block, and a block stored once is not replaced.
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::arena::{Arena, Partition};
+use super::block::{same_type, to_disk_type};
+use super::clump::{self, CLUMP_MAGIC, CLUMP_SIZE, Clump, Encoding};
+use super::score::{SCORE_SIZE, Score, unwhack_verified};
+use super::u32get;
+
+/// size of the record header of a [`LogStore`]: score, type and size
+pub const LOG_HEADER_SIZE: usize = SCORE_SIZE + 1 + 4;
+
+/// Blocks kept by score and type
+pub trait BlockStore {
+    /// Fetch the block with `score` and type `kind`, if there is one
+    ///
+    /// # Errors
+    ///
+    /// If the block is there but cannot be read back intact
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String>;
+}
+
+/// Block stores that take new blocks
+pub trait WriteStore: BlockStore {
+    /// Store `data` as the block with `score` and type `kind`
+    ///
+    /// # Errors
+    ///
+    /// If the block cannot be stored
+    fn put(&mut self, score: &Score, kind: u8, data: &[u8]) -> Result<(), String>;
+
+    /// Make the blocks stored so far durable
+    ///
+    /// # Errors
+    ///
+    /// If flushing fails
+    fn sync(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Store `data` under its own score and return the score
+    ///
+    /// # Errors
+    ///
+    /// As for [`WriteStore::put`]
+    fn write(&mut self, kind: u8, data: &[u8]) -> Result<Score, String> {
+        let score = Score::of(data);
+        self.put(&score, kind, data)?;
+        Ok(score)
+    }
+}
+
+impl<S: BlockStore + ?Sized> BlockStore for Box<S> {
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String> {
+        (**self).get(score, kind)
+    }
+}
+
+impl<S: WriteStore + ?Sized> WriteStore for Box<S> {
+    fn put(&mut self, score: &Score, kind: u8, data: &[u8]) -> Result<(), String> {
+        (**self).put(score, kind, data)
+    }
+
+    fn sync(&mut self) -> Result<(), String> {
+        (**self).sync()
+    }
+}
+
+/// Blocks kept in memory
+#[derive(Default)]
+pub struct MemStore {
+    blocks: HashMap<(Score, u8), Vec<u8>>,
+}
+
+/// Blocks kept as one file each, named by score and old type
+pub struct DirStore {
+    root: PathBuf,
+}
+
+/// Blocks appended to a single file, each after a record header
+pub struct LogStore {
+    file: File,
+    /// offset and size of each block
+    records: HashMap<(Score, u8), (u64, usize)>,
+    end: u64,
+}
+
+/// Read-only blocks of the arenas in a partition
+pub struct ArenaStore<R> {
+    part: Partition<R>,
+    arenas: Vec<Arena>,
+    /// arena and address of each block's clump
+    clumps: HashMap<(Score, u8), (usize, u64)>,
+}
+
+/// Blocks of another store, kept there as whack compressed clumps
+///
+/// The clump header records the type, encoding and uncompressed
+/// size of each block.
+pub struct WhackStore<S> {
+    store: S,
+}
+
+/// Most recently used blocks of another store
+pub struct CacheStore<S> {
+    store: S,
+    /// most blocks kept
+    max: usize,
+    /// each block and its time of last use
+    blocks: HashMap<(Score, u8), (Vec<u8>, u64)>,
+    /// blocks by time of last use
+    lru: BTreeMap<u64, (Score, u8)>,
+    clock: u64,
+    /// reads answered from the cache
+    pub hits: u64,
+    /// reads passed on to the store
+    pub misses: u64,
+}
+
+impl MemStore {
+    pub fn new() -> MemStore {
+        MemStore::default()
+    }
+
+    /// Number of blocks held
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether no blocks are held
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Number of bytes held
+    pub fn size(&self) -> usize {
+        self.blocks.values().map(Vec::len).sum()
+    }
+}
+
+impl BlockStore for MemStore {
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.blocks.get(&key(score, kind)).cloned())
+    }
+}
+
+impl WriteStore for MemStore {
+    fn put(&mut self, score: &Score, kind: u8, data: &[u8]) -> Result<(), String> {
+        self.blocks
+            .entry(key(score, kind))
+            .or_insert_with(|| data.to_vec());
+        Ok(())
+    }
+}
+
+impl DirStore {
+    /// Use directory `root`, creating it if needed
+    ///
+    /// # Errors
+    ///
+    /// If the directory cannot be created
+    pub fn open(root: impl Into<PathBuf>) -> Result<DirStore, String> {
+        let root = root.into();
+        fs::create_dir_all(&root).map_err(|e| format!("{}: {e}", root.display()))?;
+        Ok(DirStore { root })
+    }
+
+    /// File holding the block with `score` and type `kind`
+    pub fn path(&self, score: &Score, kind: u8) -> PathBuf {
+        let name = score.to_string();
+        let kind = to_disk_type(kind);
+        self.root.join(&name[..2]).join(format!("{name}.{kind}"))
+    }
+}
+
+impl BlockStore for DirStore {
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String> {
+        let path = self.path(score, kind);
+        match fs::read(&path) {
+            Ok(buf) => Ok(Some(buf)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        }
+    }
+}
+
+impl WriteStore for DirStore {
+    fn put(&mut self, score: &Score, kind: u8, data: &[u8]) -> Result<(), String> {
+        let path = self.path(score, kind);
+        if path.exists() {
+            return Ok(());
+        }
+        let dir = path.parent().unwrap();
+        let tmp = dir.join(format!("{score}.tmp"));
+        fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&tmp, data))
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+impl LogStore {
+    /// Open or create the log at `path` and index the records in it
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be opened or its last record is cut short
+    pub fn open(path: impl Into<PathBuf>) -> Result<LogStore, String> {
+        let path = path.into();
+        let err = |e: std::io::Error| format!("{}: {e}", path.display());
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(err)?;
+        let len = file.metadata().map_err(err)?.len();
+        let mut records = HashMap::new();
+        let mut end = 0;
+        let mut r = BufReader::new(&mut file);
+        while end + LOG_HEADER_SIZE as u64 <= len {
+            let mut buf = [0u8; LOG_HEADER_SIZE];
+            r.read_exact(&mut buf).map_err(err)?;
+            let score = Score(buf[..SCORE_SIZE].try_into().unwrap());
+            let size = u32get(&buf[SCORE_SIZE + 1..]) as usize;
+            let off = end + LOG_HEADER_SIZE as u64;
+            records
+                .entry(key(&score, buf[SCORE_SIZE]))
+                .or_insert((off, size));
+            r.seek_relative(size as i64).map_err(err)?;
+            end = off + size as u64;
+        }
+        if end != len {
+            return Err(format!("{}: last record cut short", path.display()));
+        }
+        Ok(LogStore { file, records, end })
+    }
+}
+
+impl BlockStore for LogStore {
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String> {
+        let Some(&(off, size)) = self.records.get(&key(score, kind)) else {
+            return Ok(None);
+        };
+        let mut buf = vec![0u8; size];
+        self.file
+            .seek(SeekFrom::Start(off))
+            .and_then(|_| self.file.read_exact(&mut buf))
+            .map_err(|e| format!("read record at {off}: {e}"))?;
+        Ok(Some(buf))
+    }
+}
+
+impl WriteStore for LogStore {
+    fn put(&mut self, score: &Score, kind: u8, data: &[u8]) -> Result<(), String> {
+        if self.records.contains_key(&key(score, kind)) {
+            return Ok(());
+        }
+        let size = u32::try_from(data.len()).map_err(|_| String::from("block too large"))?;
+        let mut buf = Vec::with_capacity(LOG_HEADER_SIZE + data.len());
+        buf.extend_from_slice(&score.0);
+        buf.push(kind);
+        buf.extend_from_slice(&size.to_be_bytes());
+        buf.extend_from_slice(data);
+        self.file
+            .seek(SeekFrom::Start(self.end))
+            .and_then(|_| self.file.write_all(&buf))
+            .map_err(|e| format!("append record: {e}"))?;
+        let off = self.end + LOG_HEADER_SIZE as u64;
+        self.records.insert(key(score, kind), (off, data.len()));
+        self.end = off + data.len() as u64;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), String> {
+        self.file.sync_all().map_err(|e| format!("sync: {e}"))
+    }
+}
+
+impl<R: Read + Seek> ArenaStore<R> {
+    /// Read the clump directories of all arenas in `part`
+    ///
+    /// # Errors
+    ///
+    /// If an arena or its directory cannot be read
+    pub fn open(mut part: Partition<R>) -> Result<ArenaStore<R>, String> {
+        let mut arenas = Vec::with_capacity(part.map.len());
+        let mut clumps = HashMap::new();
+        for n in 0..part.map.len() {
+            let arena = part.arena(n)?;
+            for e in part.clumps(&arena)? {
+                clumps
+                    .entry(key(&e.info.score, e.info.kind))
+                    .or_insert((n, e.addr));
+            }
+            arenas.push(arena);
+        }
+        Ok(ArenaStore {
+            part,
+            arenas,
+            clumps,
+        })
+    }
+}
+
+impl<R: Read + Seek> BlockStore for ArenaStore<R> {
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String> {
+        let Some(&(n, addr)) = self.clumps.get(&key(score, kind)) else {
+            return Ok(None);
+        };
+        let (_, data) = self.part.read_clump(&self.arenas[n], addr)?;
+        if Score::of(&data) != *score {
+            return Err(format!("block {score} does not match its score"));
+        }
+        Ok(Some(data))
+    }
+}
+
+impl<S: BlockStore> WhackStore<S> {
+    pub fn new(store: S) -> WhackStore<S> {
+        WhackStore { store }
+    }
+
+    /// The store holding the clumps
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    /// Give back the store holding the clumps
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+}
+
+impl<S: BlockStore> BlockStore for WhackStore<S> {
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String> {
+        match self.store.get(score, kind)? {
+            Some(c) => decode_verified(&c, score, kind),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<S: WriteStore> WriteStore for WhackStore<S> {
+    fn put(&mut self, score: &Score, kind: u8, data: &[u8]) -> Result<(), String> {
+        if Score::of(data) != *score {
+            return Err(format!("block does not match score {score}"));
+        }
+        let c = clump::encode(kind, data, CLUMP_MAGIC, 0, now())?;
+        self.store.put(score, kind, &c)
+    }
+
+    fn sync(&mut self) -> Result<(), String> {
+        self.store.sync()
+    }
+}
+
+impl<S: BlockStore> CacheStore<S> {
+    /// Cache up to `max` blocks of `store`
+    pub fn new(store: S, max: usize) -> CacheStore<S> {
+        CacheStore {
+            store,
+            max,
+            blocks: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Give back the underlying store
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    /// mark the block under `key` as just used
+    fn touch(&mut self, key: (Score, u8)) {
+        if let Some(b) = self.blocks.get_mut(&key) {
+            self.lru.remove(&b.1);
+            self.clock += 1;
+            b.1 = self.clock;
+            self.lru.insert(self.clock, key);
+        }
+    }
+}
+
+impl<S: BlockStore> BlockStore for CacheStore<S> {
+    fn get(&mut self, score: &Score, kind: u8) -> Result<Option<Vec<u8>>, String> {
+        let key = key(score, kind);
+        if let Some((data, _)) = self.blocks.get(&key) {
+            let data = data.clone();
+            self.hits += 1;
+            self.touch(key);
+            return Ok(Some(data));
+        }
+        self.misses += 1;
+        let Some(data) = self.store.get(score, kind)? else {
+            return Ok(None);
+        };
+        if self.max > 0 {
+            while self.blocks.len() >= self.max {
+                let (_, old) = self.lru.pop_first().unwrap();
+                self.blocks.remove(&old);
+            }
+            self.blocks.insert(key, (data.clone(), 0));
+            self.touch(key);
+        }
+        Ok(Some(data))
+    }
+}
+
+impl<S: WriteStore> WriteStore for CacheStore<S> {
+    fn put(&mut self, score: &Score, kind: u8, data: &[u8]) -> Result<(), String> {
+        self.store.put(score, kind, data)
+    }
+
+    fn sync(&mut self) -> Result<(), String> {
+        self.store.sync()
+    }
+}
+
+/// decode a stored clump, checking it holds `score` of type `kind`
+pub(crate) fn decode_verified(
+    buf: &[u8],
+    score: &Score,
+    kind: u8,
+) -> Result<Option<Vec<u8>>, String> {
+    if buf.len() < CLUMP_SIZE {
+        return Err(format!("block {score}: short clump"));
+    }
+    let c = Clump::unpack(buf)?;
+    if c.info.score != *score {
+        return Err(format!("block {score}: clump holds {}", c.info.score));
+    }
+    if !same_type(c.info.kind, kind) {
+        return Ok(None);
+    }
+    let data = match c.encoding {
+        Encoding::Compressed => {
+            let stored = buf
+                .get(CLUMP_SIZE..CLUMP_SIZE + c.info.size as usize)
+                .ok_or(format!("block {score}: short clump"))?;
+            unwhack_verified(stored, c.info.uncsize as usize, score)
+                .map_err(|e| format!("block {score}: {e}"))?
+        }
+        Encoding::None => {
+            let (_, data) = clump::decode(buf)?;
+            if Score::of(&data) != *score {
+                return Err(format!("block {score} does not match its score"));
+            }
+            data
+        }
+    };
+    Ok(Some(data))
+}
+
+/// what blocks are kept by: the score and the old type
+fn key(score: &Score, kind: u8) -> (Score, u8) {
+    (*score, to_disk_type(kind))
+}
+
+/// seconds since the epoch, as clumps record it
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
@@ -0,0 +1,662 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! Vac archives
+//!
+//! A vac archive is a venti tree rooted in a root block of type
+//! "vac". The root points at a directory block of three entries: the
+//! top directory's entries, its metadata, and a metadata stream
+//! holding the top directory's own [`DirEntry`].
+//!
+//! Every directory is a pair of streams. Its entry stream lists the
+//! streams of its children, and its metadata stream is a run of meta
+//! blocks of [`DirEntry`]s naming them. A file's [`DirEntry`] points
+//! at one entry, a directory's at two: `entry` and `mentry`.
+//!
+//! [`Vac`] reads an archive; [`write_vac`] makes one from a local
+//! directory tree.
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use super::block::{DATA_TYPE, DIR_TYPE, ROOT_TYPE};
+use super::entry::{ENTRY_ACTIVE, ENTRY_SIZE, Entry, ROOT_SIZE, Root};
+use super::score::Score;
+use super::store::{BlockStore, WriteStore};
+use super::{u16get, u16put, u32get, u32put, u64get};
+
+pub const META_MAGIC: u32 = 0x5656fc7a;
+/// size of a meta block header
+pub const META_HEADER_SIZE: usize = 12;
+/// size of an index entry of a meta block
+pub const META_INDEX_SIZE: usize = 4;
+pub const DIR_MAGIC: u32 = 0x1c4d9072;
+pub const DIR_VERSION: u16 = 9;
+/// the root type name of vac archives
+pub const VAC_ROOT_KIND: &str = "vac";
+
+pub const MODE_DIR: u32 = 1 << 15;
+/// mask of the permission bits of a mode
+pub const MODE_PERM: u32 = 0o777;
+
+/// Metadata of a file or directory
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DirEntry {
+    /// name within its directory
+    pub elem: String,
+    /// entry of the contents in the parent's entry stream
+    pub entry: u32,
+    pub generation: u32,
+    /// entry of the metadata stream, for directories
+    pub mentry: u32,
+    pub mgen: u32,
+    pub qid: u64,
+    pub uid: String,
+    pub gid: String,
+    /// last modifier
+    pub mid: String,
+    pub mtime: u32,
+    pub mcount: u32,
+    pub ctime: u32,
+    pub atime: u32,
+    pub mode: u32,
+}
+
+/// File or directory in an open archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VacFile {
+    pub dir: DirEntry,
+    /// contents, or the entries of a directory
+    pub source: Entry,
+    /// metadata stream of a directory
+    pub msource: Option<Entry>,
+}
+
+/// Read-only view of a vac archive
+pub struct Vac<S> {
+    store: S,
+    pub root: Root,
+    top: VacFile,
+}
+
+impl DirEntry {
+    /// Whether this is a directory
+    pub fn is_dir(&self) -> bool {
+        self.mode & MODE_DIR != 0
+    }
+
+    /// Append the packed entry to `p`
+    pub fn pack(&self, p: &mut Vec<u8>) {
+        p.extend_from_slice(&DIR_MAGIC.to_be_bytes());
+        p.extend_from_slice(&DIR_VERSION.to_be_bytes());
+        strput(p, &self.elem);
+        for v in [self.entry, self.generation, self.mentry, self.mgen] {
+            p.extend_from_slice(&v.to_be_bytes());
+        }
+        p.extend_from_slice(&self.qid.to_be_bytes());
+        strput(p, &self.uid);
+        strput(p, &self.gid);
+        strput(p, &self.mid);
+        for v in [self.mtime, self.mcount, self.ctime, self.atime, self.mode] {
+            p.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    /// Unpack an entry filling `buf`, skipping its extensions
+    ///
+    /// # Errors
+    ///
+    /// If the magic or version is wrong or the fields overrun `buf`
+    pub fn unpack(buf: &[u8]) -> Result<DirEntry, String> {
+        let mut p = Fields { buf, pos: 0 };
+        let magic = u32get(p.take(4)?);
+        if magic != DIR_MAGIC {
+            return Err(format!("bad dir entry magic {magic:#x}"));
+        }
+        let version = u16get(p.take(2)?);
+        if version != DIR_VERSION {
+            return Err(format!("unsupported dir entry version {version}"));
+        }
+        let mut de = DirEntry {
+            elem: p.string()?,
+            ..DirEntry::default()
+        };
+        de.entry = p.u32()?;
+        de.generation = p.u32()?;
+        de.mentry = p.u32()?;
+        de.mgen = p.u32()?;
+        de.qid = u64get(p.take(8)?);
+        de.uid = p.string()?;
+        de.gid = p.string()?;
+        de.mid = p.string()?;
+        de.mtime = p.u32()?;
+        de.mcount = p.u32()?;
+        de.ctime = p.u32()?;
+        de.atime = p.u32()?;
+        de.mode = p.u32()?;
+        while p.pos < buf.len() {
+            p.take(1)?;
+            let n = u16get(p.take(2)?) as usize;
+            p.take(n)?;
+        }
+        Ok(de)
+    }
+}
+
+/// Pack `entries` into a meta block of `buf.len()` bytes
+///
+/// The entries are indexed in order of their names.
+///
+/// # Errors
+///
+/// If they don't fit
+pub fn pack_meta_block(entries: &[DirEntry], buf: &mut [u8]) -> Result<(), String> {
+    let mut sorted: Vec<&DirEntry> = entries.iter().collect();
+    sorted.sort_by(|a, b| a.elem.cmp(&b.elem));
+    let n = entries.len();
+    let mut off = META_HEADER_SIZE + n * META_INDEX_SIZE;
+    if n > u16::MAX as usize || off > buf.len() {
+        return Err(format!("{n} entries overflow meta block"));
+    }
+    for (i, de) in sorted.iter().enumerate() {
+        let mut p = Vec::new();
+        de.pack(&mut p);
+        if off + p.len() > buf.len() {
+            return Err(format!("{n} entries overflow meta block"));
+        }
+        buf[off..off + p.len()].copy_from_slice(&p);
+        let x = META_HEADER_SIZE + i * META_INDEX_SIZE;
+        u16put(&mut buf[x..], off as u16);
+        u16put(&mut buf[x + 2..], p.len() as u16);
+        off += p.len();
+    }
+    u32put(buf, META_MAGIC);
+    u16put(&mut buf[4..], off as u16);
+    u16put(&mut buf[6..], 0);
+    u16put(&mut buf[8..], n as u16);
+    u16put(&mut buf[10..], n as u16);
+    Ok(())
+}
+
+/// Unpack the entries of a meta block
+///
+/// # Errors
+///
+/// If the header or an entry is damaged
+pub fn unpack_meta_block(buf: &[u8]) -> Result<Vec<DirEntry>, String> {
+    if buf.len() < META_HEADER_SIZE || u32get(buf) != META_MAGIC {
+        return Err(String::from("bad meta block"));
+    }
+    let size = u16get(&buf[4..]) as usize;
+    let maxindex = u16get(&buf[8..]) as usize;
+    let nindex = u16get(&buf[10..]) as usize;
+    if size > buf.len() || nindex > maxindex || META_HEADER_SIZE + maxindex * META_INDEX_SIZE > size
+    {
+        return Err(String::from("bad meta block header"));
+    }
+    let mut entries = Vec::with_capacity(nindex);
+    for i in 0..nindex {
+        let x = META_HEADER_SIZE + i * META_INDEX_SIZE;
+        let off = u16get(&buf[x..]) as usize;
+        let n = u16get(&buf[x + 2..]) as usize;
+        let de = buf.get(off..off + n).ok_or("meta entry outside block")?;
+        entries.push(DirEntry::unpack(de)?);
+    }
+    Ok(entries)
+}
+
+impl<S: BlockStore> Vac<S> {
+    /// Open the archive whose root block has `score`
+    ///
+    /// # Errors
+    ///
+    /// If the root or top directory cannot be read
+    pub fn open(mut store: S, score: &Score) -> Result<Vac<S>, String> {
+        let buf = store
+            .get(score, ROOT_TYPE)?
+            .ok_or(format!("no root block {score}"))?;
+        let root = Root::unpack(&buf)?;
+        if root.kind != VAC_ROOT_KIND {
+            return Err(format!("root of type {} is not a vac archive", root.kind));
+        }
+        let top = Entry {
+            generation: 0,
+            psize: root.blocksize / ENTRY_SIZE as u16 * ENTRY_SIZE as u16,
+            dsize: root.blocksize,
+            kind: DIR_TYPE,
+            flags: ENTRY_ACTIVE,
+            size: 3 * ENTRY_SIZE as u64,
+            score: root.score,
+        };
+        Vac::with_top(store, root, &top)
+    }
+
+    /// Open a tree whose top directory is the stream of `top`
+    ///
+    /// The stream holds the source, metadata source and metadata of
+    /// the top directory, as under a vac root. Fossil file systems
+    /// share this layout.
+    ///
+    /// # Errors
+    ///
+    /// If the top directory cannot be read
+    pub fn with_top(mut store: S, root: Root, top: &Entry) -> Result<Vac<S>, String> {
+        let source = source_entry(&mut store, top, 0)?;
+        let msource = source_entry(&mut store, top, 1)?;
+        let meta = source_entry(&mut store, top, 2)?;
+        let dirs = read_meta(&mut store, &meta)?;
+        let dir = dirs.into_iter().next().ok_or("no root directory entry")?;
+        let top = VacFile {
+            dir,
+            source,
+            msource: Some(msource),
+        };
+        Ok(Vac { store, root, top })
+    }
+
+    /// The top directory
+    pub fn top(&self) -> &VacFile {
+        &self.top
+    }
+
+    /// List the files in directory `dir`
+    ///
+    /// # Errors
+    ///
+    /// If `dir` is not a directory or its streams cannot be read
+    pub fn list(&mut self, dir: &VacFile) -> Result<Vec<VacFile>, String> {
+        let msource = dir
+            .msource
+            .as_ref()
+            .ok_or(format!("{} is not a directory", dir.dir.elem))?;
+        let mut files = Vec::new();
+        for de in read_meta(&mut self.store, msource)? {
+            let source = source_entry(&mut self.store, &dir.source, de.entry)?;
+            let msource = if de.is_dir() {
+                Some(source_entry(&mut self.store, &dir.source, de.mentry)?)
+            } else {
+                None
+            };
+            files.push(VacFile {
+                dir: de,
+                source,
+                msource,
+            });
+        }
+        Ok(files)
+    }
+
+    /// Find the file at slash separated `path` below the top directory
+    ///
+    /// # Errors
+    ///
+    /// If a component is missing or a directory cannot be read
+    pub fn walk(&mut self, path: &str) -> Result<VacFile, String> {
+        let mut f = self.top.clone();
+        for elem in path.split('/').filter(|e| !e.is_empty()) {
+            f = self
+                .list(&f)?
+                .into_iter()
+                .find(|c| c.dir.elem == elem)
+                .ok_or(format!("{path}: {elem} not found"))?;
+        }
+        Ok(f)
+    }
+
+    /// Write the contents of file `f` to `w`, returning the byte count
+    ///
+    /// # Errors
+    ///
+    /// If a block cannot be read or writing fails
+    pub fn read<W: Write>(&mut self, f: &VacFile, w: &mut W) -> Result<u64, String> {
+        let e = &f.source;
+        let mut left = e.size;
+        let mut bn = 0;
+        while left > 0 {
+            let block = e.block(&mut self.store, bn)?;
+            let n = left.min(block.len() as u64);
+            if n == 0 {
+                return Err(format!("{}: empty data blocks", f.dir.elem));
+            }
+            w.write_all(&block[..n as usize])
+                .map_err(|e| format!("write {}: {e}", f.dir.elem))?;
+            left -= n;
+            bn += 1;
+        }
+        Ok(e.size)
+    }
+
+    /// Read up to `count` bytes of file `f` from `offset`
+    ///
+    /// # Errors
+    ///
+    /// If the file has no data block size or a block cannot be read
+    pub fn read_at(&mut self, f: &VacFile, offset: u64, count: usize) -> Result<Vec<u8>, String> {
+        let e = &f.source;
+        let end = e.size.min(offset.saturating_add(count as u64));
+        let mut data = Vec::new();
+        let mut off = offset;
+        if e.dsize == 0 && off < end {
+            return Err(format!("{}: zero data block size", f.dir.elem));
+        }
+        while off < end {
+            let dsize = e.dsize as u64;
+            let block = e.block(&mut self.store, off / dsize)?;
+            let start = (off % dsize) as usize;
+            let n = (end - off).min(dsize - start as u64) as usize;
+            data.extend_from_slice(&block[start..start + n]);
+            off += n as u64;
+        }
+        Ok(data)
+    }
+
+    /// Restore directory `dir` and everything below it into `path`
+    ///
+    /// Permissions are kept; owners and times are not. Files already
+    /// in the way are replaced, but symbolic links below `path` are
+    /// neither followed nor replaced.
+    ///
+    /// # Errors
+    ///
+    /// If reading the archive or writing the files fails, or a
+    /// symbolic link is in the way
+    pub fn restore(&mut self, dir: &VacFile, path: &Path) -> Result<(), String> {
+        let err = |p: &Path, e: std::io::Error| format!("{}: {e}", p.display());
+        fs::create_dir_all(path).map_err(|e| err(path, e))?;
+        for f in self.list(dir)? {
+            if matches!(f.dir.elem.as_str(), "" | "." | "..") || f.dir.elem.contains('/') {
+                return Err(format!("bad file name {:?}", f.dir.elem));
+            }
+            let p = path.join(&f.dir.elem);
+            if fs::symlink_metadata(&p).is_ok_and(|m| m.file_type().is_symlink()) {
+                return Err(format!("{}: symbolic link in the way", p.display()));
+            }
+            if f.dir.is_dir() {
+                self.restore(&f, &p)?;
+            } else {
+                match fs::remove_file(&p) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(err(&p, e)),
+                    _ => {}
+                }
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&p)
+                    .map_err(|e| err(&p, e))?;
+                let mut w = std::io::BufWriter::new(file);
+                self.read(&f, &mut w)?;
+                w.flush().map_err(|e| err(&p, e))?;
+            }
+            set_mode(&p, f.dir.mode).map_err(|e| err(&p, e))?;
+        }
+        Ok(())
+    }
+
+    /// Give back the block store
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+}
+
+/// Archive the directory tree at `path` into `store`
+///
+/// Files are cut into blocks of `blocksize` bytes. Returns the score
+/// of the root block, named `name`. Only directories and regular
+/// files are archived.
+///
+/// # Errors
+///
+/// If `blocksize` is too small, a file cannot be read or a block
+/// cannot be stored
+pub fn write_vac<S: WriteStore>(
+    store: &mut S,
+    path: &Path,
+    name: &str,
+    blocksize: u16,
+) -> Result<Score, String> {
+    if (blocksize as usize) < 2 * ENTRY_SIZE {
+        return Err(format!("block size {blocksize} too small"));
+    }
+    let mut w = VacWriter {
+        store,
+        blocksize,
+        psize: blocksize / ENTRY_SIZE as u16 * ENTRY_SIZE as u16,
+        qid: 0,
+    };
+    let md = fs::metadata(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut top = w.dir_entry(&md, "/");
+    let (source, msource) = w.write_dir(path)?;
+    top.mentry = 1;
+    let meta = w.write_meta(&[top])?;
+    let mut p = vec![0u8; 3 * ENTRY_SIZE];
+    for (i, e) in [source, msource, meta].iter().enumerate() {
+        e.pack(&mut p[i * ENTRY_SIZE..]);
+    }
+    let score = w.store.write(DIR_TYPE, &p)?;
+    let mut root = vec![0u8; ROOT_SIZE];
+    Root {
+        name: String::from(name),
+        kind: String::from(VAC_ROOT_KIND),
+        score,
+        blocksize,
+        prev: Score::ZERO,
+    }
+    .pack(&mut root);
+    w.store.write(ROOT_TYPE, &root)
+}
+
+/// state of [`write_vac`]
+struct VacWriter<'a, S> {
+    store: &'a mut S,
+    blocksize: u16,
+    /// size of pointer blocks and entry blocks
+    psize: u16,
+    /// last qid handed out
+    qid: u64,
+}
+
+impl<S: WriteStore> VacWriter<'_, S> {
+    /// store the entry and metadata streams of directory `path`
+    fn write_dir(&mut self, path: &Path) -> Result<(Entry, Entry), String> {
+        let err = |p: &Path, e: std::io::Error| format!("{}: {e}", p.display());
+        let mut names = Vec::new();
+        for d in fs::read_dir(path).map_err(|e| err(path, e))? {
+            let d = d.map_err(|e| err(path, e))?;
+            names.push(d.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        let mut entries = Vec::new();
+        let mut dirs = Vec::new();
+        for elem in names {
+            let p = path.join(&elem);
+            let md = fs::symlink_metadata(&p).map_err(|e| err(&p, e))?;
+            let ft = md.file_type();
+            if !ft.is_dir() && !ft.is_file() {
+                continue;
+            }
+            let mut de = self.dir_entry(&md, &elem);
+            de.entry = entries.len() as u32;
+            if ft.is_dir() {
+                let (source, msource) = self.write_dir(&p)?;
+                de.mentry = de.entry + 1;
+                entries.push(source);
+                entries.push(msource);
+            } else {
+                let mut f = std::io::BufReader::new(fs::File::open(&p).map_err(|e| err(&p, e))?);
+                entries.push(Entry::write(
+                    self.store,
+                    DATA_TYPE,
+                    &mut f,
+                    self.psize,
+                    self.blocksize,
+                )?);
+            }
+            dirs.push(de);
+        }
+        let mut p = vec![0u8; entries.len() * ENTRY_SIZE];
+        for (i, e) in entries.iter().enumerate() {
+            e.pack(&mut p[i * ENTRY_SIZE..]);
+        }
+        let source = Entry::write(self.store, DIR_TYPE, &mut &p[..], self.psize, self.psize)?;
+        Ok((source, self.write_meta(&dirs)?))
+    }
+
+    /// store a metadata stream of meta blocks holding `dirs`
+    fn write_meta(&mut self, dirs: &[DirEntry]) -> Result<Entry, String> {
+        let bs = self.blocksize as usize;
+        let mut stream = Vec::new();
+        let mut block: Vec<DirEntry> = Vec::new();
+        let mut used = META_HEADER_SIZE;
+        for de in dirs {
+            let mut p = Vec::new();
+            de.pack(&mut p);
+            let n = META_INDEX_SIZE + p.len();
+            if META_HEADER_SIZE + n > bs {
+                return Err(format!("dir entry of {} too large", de.elem));
+            }
+            if used + n > bs {
+                let mut buf = vec![0u8; bs];
+                pack_meta_block(&block, &mut buf)?;
+                stream.extend_from_slice(&buf);
+                block.clear();
+                used = META_HEADER_SIZE;
+            }
+            block.push(de.clone());
+            used += n;
+        }
+        if !block.is_empty() {
+            let mut buf = vec![0u8; bs];
+            pack_meta_block(&block, &mut buf)?;
+            stream.extend_from_slice(&buf);
+        }
+        Entry::write(
+            self.store,
+            DATA_TYPE,
+            &mut &stream[..],
+            self.psize,
+            self.blocksize,
+        )
+    }
+
+    /// describe the file named `elem` with metadata `md`
+    fn dir_entry(&mut self, md: &fs::Metadata, elem: &str) -> DirEntry {
+        let secs = |t: std::io::Result<std::time::SystemTime>| {
+            t.ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs() as u32)
+        };
+        let (uid, gid, perm) = owner(md);
+        self.qid += 1;
+        DirEntry {
+            elem: String::from(elem),
+            qid: self.qid,
+            mid: uid.clone(),
+            uid,
+            gid,
+            mtime: secs(md.modified()),
+            ctime: ctime(md).unwrap_or_else(|| secs(md.modified())),
+            atime: secs(md.accessed()),
+            mode: perm | if md.is_dir() { MODE_DIR } else { 0 },
+            ..DirEntry::default()
+        }
+    }
+}
+
+#[cfg(unix)]
+fn owner(md: &fs::Metadata) -> (String, String, u32) {
+    use std::os::unix::fs::MetadataExt;
+    (
+        md.uid().to_string(),
+        md.gid().to_string(),
+        md.mode() & MODE_PERM,
+    )
+}
+
+/// time of the last change to the file's metadata
+#[cfg(unix)]
+fn ctime(md: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    u32::try_from(md.ctime()).ok()
+}
+
+#[cfg(not(unix))]
+fn ctime(_md: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(not(unix))]
+fn owner(md: &fs::Metadata) -> (String, String, u32) {
+    let perm = if md.permissions().readonly() {
+        0o555
+    } else {
+        0o755
+    };
+    (String::from("none"), String::from("none"), perm)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & MODE_PERM))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// read entry `n` of the entry stream `dir`
+fn source_entry<S: BlockStore>(store: &mut S, dir: &Entry, n: u32) -> Result<Entry, String> {
+    let epb = (dir.dsize as usize / ENTRY_SIZE) as u64;
+    if epb == 0 || (n as u64 + 1) * ENTRY_SIZE as u64 > dir.size {
+        return Err(format!("no entry {n}"));
+    }
+    let block = dir.block(store, n as u64 / epb)?;
+    let off = (n as u64 % epb) as usize * ENTRY_SIZE;
+    let e = Entry::unpack(&block[off..off + ENTRY_SIZE]);
+    if e.flags & ENTRY_ACTIVE == 0 {
+        return Err(format!("entry {n} not active"));
+    }
+    Ok(e)
+}
+
+/// read all dir entries of a metadata stream
+fn read_meta<S: BlockStore>(store: &mut S, meta: &Entry) -> Result<Vec<DirEntry>, String> {
+    let mut dirs = Vec::new();
+    if meta.dsize == 0 {
+        return Ok(dirs);
+    }
+    for bn in 0..meta.size.div_ceil(meta.dsize as u64) {
+        dirs.extend(unpack_meta_block(&meta.block(store, bn)?)?);
+    }
+    Ok(dirs)
+}
+
+fn strput(p: &mut Vec<u8>, s: &str) {
+    p.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    p.extend_from_slice(s.as_bytes());
+}
+
+/// cursor over the fields of a dir entry
+struct Fields<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() - self.pos < n {
+            return Err(String::from("short dir entry"));
+        }
+        self.pos += n;
+        Ok(&self.buf[self.pos - n..self.pos])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32get(self.take(4)?))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let n = u16get(self.take(2)?) as usize;
+        Ok(String::from_utf8_lossy(self.take(n)?).into_owned())
+    }
+}
@@ -0,0 +1,816 @@
+// Copyright 2024-2026 by Michael Stroucken
+//! 9P2000 file server for vac archives
+//!
+//! Serves an open [`Vac`] read-only over any stream, such as a TCP
+//! or Unix socket connection. Blocks are fetched from the archive's
+//! storage as files are read; wrapping the storage in a
+//! [`CacheStore`](super::store::CacheStore) keeps recently used
+//! blocks decoded.
+//!
+//! Unlike venti, 9P integers are little-endian. Every message is a
+//! four byte size, counting itself, followed by the type and a tag.
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+
+use super::store::BlockStore;
+use super::vac::{MODE_PERM, Vac, VacFile};
+
+pub const TVERSION: u8 = 100;
+pub const RVERSION: u8 = 101;
+pub const TAUTH: u8 = 102;
+pub const TATTACH: u8 = 104;
+pub const RATTACH: u8 = 105;
+pub const RERROR: u8 = 107;
+pub const TFLUSH: u8 = 108;
+pub const RFLUSH: u8 = 109;
+pub const TWALK: u8 = 110;
+pub const RWALK: u8 = 111;
+pub const TOPEN: u8 = 112;
+pub const ROPEN: u8 = 113;
+pub const TCREATE: u8 = 114;
+pub const TREAD: u8 = 116;
+pub const RREAD: u8 = 117;
+pub const TWRITE: u8 = 118;
+pub const TCLUNK: u8 = 120;
+pub const RCLUNK: u8 = 121;
+pub const TREMOVE: u8 = 122;
+pub const TSTAT: u8 = 124;
+pub const RSTAT: u8 = 125;
+pub const TWSTAT: u8 = 126;
+
+/// tag of version messages
+pub const NOTAG: u16 = 0xffff;
+/// fid meaning no fid
+pub const NOFID: u32 = 0xffff_ffff;
+/// protocol version spoken
+pub const VERSION9P: &str = "9P2000";
+/// largest message the server offers to handle
+pub const MAX_MSIZE: u32 = 8192 + IOHDRSZ;
+/// room for the header of read and write messages
+pub const IOHDRSZ: u32 = 24;
+/// qid type of directories
+pub const QTDIR: u8 = 0x80;
+/// mode bit of directories
+pub const DMDIR: u32 = 0x8000_0000;
+/// most names a walk may take
+pub const MAXWELEM: usize = 16;
+/// open for reading; the other access modes are refused
+pub const OREAD: u8 = 0;
+/// mask of the access mode in an open mode
+pub const OACCESS: u8 = 3;
+/// truncate on open, refused
+pub const OTRUNC: u8 = 0x10;
+
+/// Unique identification of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Qid {
+    pub kind: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+/// File attributes
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Stat {
+    pub kind: u16,
+    pub dev: u32,
+    pub qid: Qid,
+    pub mode: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub length: u64,
+    pub name: String,
+    pub uid: String,
+    pub gid: String,
+    pub muid: String,
+}
+
+/// Requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tmsg {
+    Tversion {
+        msize: u32,
+        version: String,
+    },
+    Tauth {
+        afid: u32,
+        uname: String,
+        aname: String,
+    },
+    Tattach {
+        fid: u32,
+        afid: u32,
+        uname: String,
+        aname: String,
+    },
+    Tflush {
+        oldtag: u16,
+    },
+    Twalk {
+        fid: u32,
+        newfid: u32,
+        wnames: Vec<String>,
+    },
+    Topen {
+        fid: u32,
+        mode: u8,
+    },
+    Tcreate {
+        fid: u32,
+        name: String,
+        perm: u32,
+        mode: u8,
+    },
+    Tread {
+        fid: u32,
+        offset: u64,
+        count: u32,
+    },
+    Twrite {
+        fid: u32,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Tclunk {
+        fid: u32,
+    },
+    Tremove {
+        fid: u32,
+    },
+    Tstat {
+        fid: u32,
+    },
+    Twstat {
+        fid: u32,
+        stat: Vec<u8>,
+    },
+}
+
+/// Replies a read-only server sends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rmsg {
+    Rversion { msize: u32, version: String },
+    Rerror(String),
+    Rattach(Qid),
+    Rflush,
+    Rwalk(Vec<Qid>),
+    Ropen { qid: Qid, iounit: u32 },
+    Rread(Vec<u8>),
+    Rclunk,
+    Rstat(Stat),
+}
+
+impl Qid {
+    fn put(&self, p: &mut Vec<u8>) {
+        p.push(self.kind);
+        p.extend_from_slice(&self.version.to_le_bytes());
+        p.extend_from_slice(&self.path.to_le_bytes());
+    }
+
+    fn get(f: &mut Fields) -> Result<Qid, String> {
+        Ok(Qid {
+            kind: f.u8()?,
+            version: f.u32()?,
+            path: f.u64()?,
+        })
+    }
+}
+
+impl Stat {
+    /// Append the packed stat, with its size, to `p`
+    pub fn pack(&self, p: &mut Vec<u8>) {
+        let start = p.len();
+        p.extend_from_slice(&[0, 0]);
+        p.extend_from_slice(&self.kind.to_le_bytes());
+        p.extend_from_slice(&self.dev.to_le_bytes());
+        self.qid.put(p);
+        for v in [self.mode, self.atime, self.mtime] {
+            p.extend_from_slice(&v.to_le_bytes());
+        }
+        p.extend_from_slice(&self.length.to_le_bytes());
+        for s in [&self.name, &self.uid, &self.gid, &self.muid] {
+            strput(p, s);
+        }
+        let n = (p.len() - start - 2) as u16;
+        p[start..start + 2].copy_from_slice(&n.to_le_bytes());
+    }
+
+    /// Unpack a stat from the start of `buf`, returning it and its packed size
+    ///
+    /// # Errors
+    ///
+    /// If `buf` is too short
+    pub fn unpack(buf: &[u8]) -> Result<(Stat, usize), String> {
+        let mut f = Fields { buf, pos: 0 };
+        let n = f.u16()? as usize;
+        let mut f = Fields {
+            buf: f.take(n)?,
+            pos: 0,
+        };
+        let st = Stat {
+            kind: f.u16()?,
+            dev: f.u32()?,
+            qid: Qid::get(&mut f)?,
+            mode: f.u32()?,
+            atime: f.u32()?,
+            mtime: f.u32()?,
+            length: f.u64()?,
+            name: f.string()?,
+            uid: f.string()?,
+            gid: f.string()?,
+            muid: f.string()?,
+        };
+        Ok((st, n + 2))
+    }
+}
+
+impl Tmsg {
+    /// Pack into a whole message with size and `tag`
+    pub fn pack(&self, tag: u16) -> Vec<u8> {
+        let mut p = Vec::new();
+        let t = match self {
+            Tmsg::Tversion { msize, version } => {
+                p.extend_from_slice(&msize.to_le_bytes());
+                strput(&mut p, version);
+                TVERSION
+            }
+            Tmsg::Tauth { afid, uname, aname } => {
+                p.extend_from_slice(&afid.to_le_bytes());
+                strput(&mut p, uname);
+                strput(&mut p, aname);
+                TAUTH
+            }
+            Tmsg::Tattach {
+                fid,
+                afid,
+                uname,
+                aname,
+            } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                p.extend_from_slice(&afid.to_le_bytes());
+                strput(&mut p, uname);
+                strput(&mut p, aname);
+                TATTACH
+            }
+            Tmsg::Tflush { oldtag } => {
+                p.extend_from_slice(&oldtag.to_le_bytes());
+                TFLUSH
+            }
+            Tmsg::Twalk {
+                fid,
+                newfid,
+                wnames,
+            } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                p.extend_from_slice(&newfid.to_le_bytes());
+                p.extend_from_slice(&(wnames.len() as u16).to_le_bytes());
+                for w in wnames {
+                    strput(&mut p, w);
+                }
+                TWALK
+            }
+            Tmsg::Topen { fid, mode } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                p.push(*mode);
+                TOPEN
+            }
+            Tmsg::Tcreate {
+                fid,
+                name,
+                perm,
+                mode,
+            } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                strput(&mut p, name);
+                p.extend_from_slice(&perm.to_le_bytes());
+                p.push(*mode);
+                TCREATE
+            }
+            Tmsg::Tread { fid, offset, count } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                p.extend_from_slice(&offset.to_le_bytes());
+                p.extend_from_slice(&count.to_le_bytes());
+                TREAD
+            }
+            Tmsg::Twrite { fid, offset, data } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                p.extend_from_slice(&offset.to_le_bytes());
+                p.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                p.extend_from_slice(data);
+                TWRITE
+            }
+            Tmsg::Tclunk { fid } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                TCLUNK
+            }
+            Tmsg::Tremove { fid } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                TREMOVE
+            }
+            Tmsg::Tstat { fid } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                TSTAT
+            }
+            Tmsg::Twstat { fid, stat } => {
+                p.extend_from_slice(&fid.to_le_bytes());
+                p.extend_from_slice(&(stat.len() as u16).to_le_bytes());
+                p.extend_from_slice(stat);
+                TWSTAT
+            }
+        };
+        frame(t, tag, &p)
+    }
+
+    /// Unpack a whole message, returning its tag
+    ///
+    /// # Errors
+    ///
+    /// If the type is unknown or the fields don't fill the message
+    pub fn unpack(buf: &[u8]) -> Result<(u16, Tmsg), String> {
+        let (t, tag, mut f) = unframe(buf)?;
+        let m = match t {
+            TVERSION => Tmsg::Tversion {
+                msize: f.u32()?,
+                version: f.string()?,
+            },
+            TAUTH => Tmsg::Tauth {
+                afid: f.u32()?,
+                uname: f.string()?,
+                aname: f.string()?,
+            },
+            TATTACH => Tmsg::Tattach {
+                fid: f.u32()?,
+                afid: f.u32()?,
+                uname: f.string()?,
+                aname: f.string()?,
+            },
+            TFLUSH => Tmsg::Tflush { oldtag: f.u16()? },
+            TWALK => {
+                let fid = f.u32()?;
+                let newfid = f.u32()?;
+                let n = f.u16()?;
+                let wnames = (0..n).map(|_| f.string()).collect::<Result<_, _>>()?;
+                Tmsg::Twalk {
+                    fid,
+                    newfid,
+                    wnames,
+                }
+            }
+            TOPEN => Tmsg::Topen {
+                fid: f.u32()?,
+                mode: f.u8()?,
+            },
+            TCREATE => Tmsg::Tcreate {
+                fid: f.u32()?,
+                name: f.string()?,
+                perm: f.u32()?,
+                mode: f.u8()?,
+            },
+            TREAD => Tmsg::Tread {
+                fid: f.u32()?,
+                offset: f.u64()?,
+                count: f.u32()?,
+            },
+            TWRITE => {
+                let fid = f.u32()?;
+                let offset = f.u64()?;
+                let n = f.u32()? as usize;
+                Tmsg::Twrite {
+                    fid,
+                    offset,
+                    data: f.take(n)?.to_vec(),
+                }
+            }
+            TCLUNK => Tmsg::Tclunk { fid: f.u32()? },
+            TREMOVE => Tmsg::Tremove { fid: f.u32()? },
+            TSTAT => Tmsg::Tstat { fid: f.u32()? },
+            TWSTAT => {
+                let fid = f.u32()?;
+                let n = f.u16()? as usize;
+                Tmsg::Twstat {
+                    fid,
+                    stat: f.take(n)?.to_vec(),
+                }
+            }
+            t => return Err(format!("unknown message type {t}")),
+        };
+        f.end()?;
+        Ok((tag, m))
+    }
+}
+
+impl Rmsg {
+    /// Pack into a whole message with size and `tag`
+    pub fn pack(&self, tag: u16) -> Vec<u8> {
+        let mut p = Vec::new();
+        let t = match self {
+            Rmsg::Rversion { msize, version } => {
+                p.extend_from_slice(&msize.to_le_bytes());
+                strput(&mut p, version);
+                RVERSION
+            }
+            Rmsg::Rerror(e) => {
+                strput(&mut p, e);
+                RERROR
+            }
+            Rmsg::Rattach(qid) => {
+                qid.put(&mut p);
+                RATTACH
+            }
+            Rmsg::Rflush => RFLUSH,
+            Rmsg::Rwalk(qids) => {
+                p.extend_from_slice(&(qids.len() as u16).to_le_bytes());
+                for q in qids {
+                    q.put(&mut p);
+                }
+                RWALK
+            }
+            Rmsg::Ropen { qid, iounit } => {
+                qid.put(&mut p);
+                p.extend_from_slice(&iounit.to_le_bytes());
+                ROPEN
+            }
+            Rmsg::Rread(data) => {
+                p.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                p.extend_from_slice(data);
+                RREAD
+            }
+            Rmsg::Rclunk => RCLUNK,
+            Rmsg::Rstat(st) => {
+                let mut s = Vec::new();
+                st.pack(&mut s);
+                p.extend_from_slice(&(s.len() as u16).to_le_bytes());
+                p.extend_from_slice(&s);
+                RSTAT
+            }
+        };
+        frame(t, tag, &p)
+    }
+
+    /// Unpack a whole message, returning its tag
+    ///
+    /// # Errors
+    ///
+    /// If the type is unknown or the fields don't fill the message
+    pub fn unpack(buf: &[u8]) -> Result<(u16, Rmsg), String> {
+        let (t, tag, mut f) = unframe(buf)?;
+        let m = match t {
+            RVERSION => Rmsg::Rversion {
+                msize: f.u32()?,
+                version: f.string()?,
+            },
+            RERROR => Rmsg::Rerror(f.string()?),
+            RATTACH => Rmsg::Rattach(Qid::get(&mut f)?),
+            RFLUSH => Rmsg::Rflush,
+            RWALK => {
+                let n = f.u16()?;
+                Rmsg::Rwalk((0..n).map(|_| Qid::get(&mut f)).collect::<Result<_, _>>()?)
+            }
+            ROPEN => Rmsg::Ropen {
+                qid: Qid::get(&mut f)?,
+                iounit: f.u32()?,
+            },
+            RREAD => {
+                let n = f.u32()? as usize;
+                Rmsg::Rread(f.take(n)?.to_vec())
+            }
+            RCLUNK => Rmsg::Rclunk,
+            RSTAT => {
+                let n = f.u16()? as usize;
+                Rmsg::Rstat(Stat::unpack(f.take(n)?)?.0)
+            }
+            t => return Err(format!("unknown message type {t}")),
+        };
+        f.end()?;
+        Ok((tag, m))
+    }
+}
+
+/// Read one whole message of at most `msize` bytes
+///
+/// Returns `None` if the connection ended cleanly before a message.
+///
+/// # Errors
+///
+/// If reading fails partway or the message is too large
+pub fn read_msg<R: Read>(r: &mut R, msize: u32) -> Result<Option<Vec<u8>>, String> {
+    let mut size = [0u8; 4];
+    let n = r
+        .read(&mut size[..1])
+        .map_err(|e| format!("read message: {e}"))?;
+    if n == 0 {
+        return Ok(None);
+    }
+    r.read_exact(&mut size[1..])
+        .map_err(|e| format!("read message: {e}"))?;
+    let n = u32::from_le_bytes(size);
+    if !(7..=msize).contains(&n) {
+        return Err(format!("bad message size {n}"));
+    }
+    let mut buf = vec![0u8; n as usize];
+    buf[..4].copy_from_slice(&size);
+    r.read_exact(&mut buf[4..])
+        .map_err(|e| format!("read message: {e}"))?;
+    Ok(Some(buf))
+}
+
+/// state of a fid
+struct Fid {
+    /// files from the top directory down to this one
+    path: Vec<VacFile>,
+    open: bool,
+    /// packed stats of an open directory and how far they were read
+    dir: Option<DirRead>,
+}
+
+struct DirRead {
+    stats: Vec<Vec<u8>>,
+    next: usize,
+    offset: u64,
+}
+
+/// Serve the archive on one connection until the client hangs up
+///
+/// # Errors
+///
+/// If the connection fails or the client sends a malformed message
+pub fn serve<S: BlockStore, C: Read + Write>(vac: &mut Vac<S>, conn: C) -> Result<(), String> {
+    let mut conn = BufReader::new(conn);
+    let mut fids: HashMap<u32, Fid> = HashMap::new();
+    let mut msize = MAX_MSIZE;
+    while let Some(buf) = read_msg(&mut conn, msize)? {
+        let (tag, t) = Tmsg::unpack(&buf)?;
+        let r = match t {
+            Tmsg::Tversion { msize: m, version } => {
+                fids.clear();
+                msize = m.clamp(IOHDRSZ + 1, MAX_MSIZE);
+                let version = if version.starts_with(VERSION9P) {
+                    VERSION9P
+                } else {
+                    "unknown"
+                };
+                Rmsg::Rversion {
+                    msize,
+                    version: String::from(version),
+                }
+            }
+            t => answer(vac, &mut fids, msize, t).unwrap_or_else(Rmsg::Rerror),
+        };
+        conn.get_mut()
+            .write_all(&r.pack(tag))
+            .and_then(|_| conn.get_mut().flush())
+            .map_err(|e| format!("write message: {e}"))?;
+    }
+    Ok(())
+}
+
+/// handle a request other than version
+fn answer<S: BlockStore>(
+    vac: &mut Vac<S>,
+    fids: &mut HashMap<u32, Fid>,
+    msize: u32,
+    t: Tmsg,
+) -> Result<Rmsg, String> {
+    match t {
+        Tmsg::Tversion { .. } => unreachable!(),
+        Tmsg::Tauth { .. } => Err(String::from("authentication not required")),
+        Tmsg::Tattach { fid, afid, .. } => {
+            if afid != NOFID {
+                return Err(String::from("authentication not required"));
+            }
+            if fids.contains_key(&fid) {
+                return Err(String::from("fid in use"));
+            }
+            let top = vac.top().clone();
+            let qid = qid(&top);
+            fids.insert(
+                fid,
+                Fid {
+                    path: vec![top],
+                    open: false,
+                    dir: None,
+                },
+            );
+            Ok(Rmsg::Rattach(qid))
+        }
+        Tmsg::Tflush { .. } => Ok(Rmsg::Rflush),
+        Tmsg::Twalk {
+            fid,
+            newfid,
+            wnames,
+        } => {
+            if wnames.len() > MAXWELEM {
+                return Err(format!("too many names in walk: {}", wnames.len()));
+            }
+            let f = fids.get(&fid).ok_or("unknown fid")?;
+            if f.open {
+                return Err(String::from("walk of open fid"));
+            }
+            if newfid != fid && fids.contains_key(&newfid) {
+                return Err(String::from("fid in use"));
+            }
+            let mut path = f.path.clone();
+            let mut qids = Vec::new();
+            for (i, w) in wnames.iter().enumerate() {
+                let cur = path.last().unwrap();
+                let next = if !cur.dir.is_dir() {
+                    Err(String::from("not a directory"))
+                } else if w == ".." {
+                    Ok(None)
+                } else {
+                    vac.list(cur).and_then(|cs| {
+                        cs.into_iter()
+                            .find(|c| c.dir.elem == *w)
+                            .map(Some)
+                            .ok_or(format!("{w}: file does not exist"))
+                    })
+                };
+                match next {
+                    Ok(Some(c)) => path.push(c),
+                    Ok(None) => {
+                        if path.len() > 1 {
+                            path.pop();
+                        }
+                    }
+                    Err(e) if i == 0 => return Err(e),
+                    Err(_) => return Ok(Rmsg::Rwalk(qids)),
+                }
+                qids.push(qid(path.last().unwrap()));
+            }
+            fids.insert(
+                newfid,
+                Fid {
+                    path,
+                    open: false,
+                    dir: None,
+                },
+            );
+            Ok(Rmsg::Rwalk(qids))
+        }
+        Tmsg::Topen { fid, mode } => {
+            let f = fids.get_mut(&fid).ok_or("unknown fid")?;
+            if f.open {
+                return Err(String::from("fid already open"));
+            }
+            if mode & OACCESS != OREAD || mode & OTRUNC != 0 {
+                return Err(String::from("read-only file system"));
+            }
+            f.open = true;
+            Ok(Rmsg::Ropen {
+                qid: qid(f.path.last().unwrap()),
+                iounit: msize - IOHDRSZ,
+            })
+        }
+        Tmsg::Tread { fid, offset, count } => {
+            let f = fids.get_mut(&fid).ok_or("unknown fid")?;
+            if !f.open {
+                return Err(String::from("fid not open"));
+            }
+            let count = count.min(msize - IOHDRSZ) as usize;
+            let file = f.path.last().unwrap().clone();
+            if !file.dir.is_dir() {
+                return vac.read_at(&file, offset, count).map(Rmsg::Rread);
+            }
+            if offset == 0 || f.dir.is_none() {
+                let stats = vac
+                    .list(&file)?
+                    .iter()
+                    .map(|c| {
+                        let mut p = Vec::new();
+                        stat(c).pack(&mut p);
+                        p
+                    })
+                    .collect();
+                f.dir = Some(DirRead {
+                    stats,
+                    next: 0,
+                    offset: 0,
+                });
+            }
+            let d = f.dir.as_mut().unwrap();
+            if offset != d.offset {
+                return Err(String::from("bad offset in directory read"));
+            }
+            let mut data = Vec::new();
+            while let Some(s) = d.stats.get(d.next) {
+                if data.len() + s.len() > count {
+                    break;
+                }
+                data.extend_from_slice(s);
+                d.next += 1;
+            }
+            if data.is_empty() && d.next < d.stats.len() {
+                return Err(String::from("read count too small for directory entry"));
+            }
+            d.offset += data.len() as u64;
+            Ok(Rmsg::Rread(data))
+        }
+        Tmsg::Tclunk { fid } => {
+            fids.remove(&fid).ok_or("unknown fid")?;
+            Ok(Rmsg::Rclunk)
+        }
+        Tmsg::Tremove { fid } => {
+            fids.remove(&fid).ok_or("unknown fid")?;
+            Err(String::from("read-only file system"))
+        }
+        Tmsg::Tstat { fid } => {
+            let f = fids.get(&fid).ok_or("unknown fid")?;
+            Ok(Rmsg::Rstat(stat(f.path.last().unwrap())))
+        }
+        Tmsg::Tcreate { .. } | Tmsg::Twrite { .. } | Tmsg::Twstat { .. } => {
+            Err(String::from("read-only file system"))
+        }
+    }
+}
+
+fn qid(f: &VacFile) -> Qid {
+    Qid {
+        kind: if f.dir.is_dir() { QTDIR } else { 0 },
+        version: f.dir.mcount,
+        path: f.dir.qid,
+    }
+}
+
+fn stat(f: &VacFile) -> Stat {
+    let d = &f.dir;
+    Stat {
+        qid: qid(f),
+        mode: (d.mode & MODE_PERM) | if d.is_dir() { DMDIR } else { 0 },
+        atime: d.atime,
+        mtime: d.mtime,
+        length: if d.is_dir() { 0 } else { f.source.size },
+        name: d.elem.clone(),
+        uid: d.uid.clone(),
+        gid: d.gid.clone(),
+        muid: d.mid.clone(),
+        ..Stat::default()
+    }
+}
+
+/// add size, type and tag to a message body
+fn frame(t: u8, tag: u16, body: &[u8]) -> Vec<u8> {
+    let mut p = Vec::with_capacity(7 + body.len());
+    p.extend_from_slice(&((7 + body.len()) as u32).to_le_bytes());
+    p.push(t);
+    p.extend_from_slice(&tag.to_le_bytes());
+    p.extend_from_slice(body);
+    p
+}
+
+/// split a whole message into type, tag and body
+fn unframe(buf: &[u8]) -> Result<(u8, u16, Fields<'_>), String> {
+    if buf.len() < 7 || u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize != buf.len() {
+        return Err(String::from("bad message size"));
+    }
+    let tag = u16::from_le_bytes([buf[5], buf[6]]);
+    Ok((buf[4], tag, Fields { buf, pos: 7 }))
+}
+
+fn strput(p: &mut Vec<u8>, s: &str) {
+    p.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    p.extend_from_slice(s.as_bytes());
+}
+
+/// cursor over the little-endian fields of a message
+struct Fields<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() - self.pos < n {
+            return Err(String::from("short message"));
+        }
+        self.pos += n;
+        Ok(&self.buf[self.pos - n..self.pos])
+    }
+
+    fn end(&self) -> Result<(), String> {
+        if self.pos != self.buf.len() {
+            return Err(String::from("message too long"));
+        }
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let n = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(n)?).into_owned())
+    }
+}
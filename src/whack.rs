@@ -20,6 +20,7 @@ pub struct Whack {
 }
 
 /// Collect status from compression
+#[derive(Debug, Default, Clone)]
 pub struct Stats {
     pub statbytes: usize,
     pub statoutbytes: usize,
@@ -30,11 +31,42 @@ pub struct Stats {
     pub statlenbits: usize,
 }
 
+impl Stats {
+    /// Add the counts of `other`
+    pub fn add(&mut self, other: &Stats) {
+        self.statbytes += other.statbytes;
+        self.statoutbytes += other.statoutbytes;
+        self.statlits += other.statlits;
+        self.statmatches += other.statmatches;
+        self.statlitbits += other.statlitbits;
+        self.statoffbits += other.statoffbits;
+        self.statlenbits += other.statlenbits;
+    }
+}
+
+/// Why compression of a block was abandoned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bail {
+    /// the block is shorter than a match
+    TooSmall,
+    /// too few matches by the halfway point
+    Halfway,
+    /// the output would be larger than the block
+    Expansion,
+}
+
 struct DictLookup {
     pub len: u16,
     pub off: u16,
 }
 
+/// level used by [`whackblock`], as venti does
+pub const DEFAULT_LEVEL: u8 = 6;
+/// lowest level that searches less; lower levels act the same
+pub const MIN_LEVEL: u8 = 1;
+/// highest level that searches further; higher levels act the same
+pub const MAX_LEVEL: u8 = 11;
+
 /// Create a compressor state object
 pub fn whackinit(level: u8) -> Whack {
     let mut thwmaxcheck;
@@ -51,6 +83,15 @@ pub fn whackinit(level: u8) -> Whack {
     }
 }
 
+/// how far dictionary position `then` is behind `now`
+///
+/// Positions start at `2 * WHACK_MAX_OFF` and wrap around, as the
+/// ushorts of the C code do, so the distance wraps too.
+#[inline]
+fn dict_offset(now: u16, then: u16) -> u16 {
+    now.wrapping_sub(then)
+}
+
 /// find a string in the dictionary
 fn whackmatch(
     w: &Whack,
@@ -88,7 +129,7 @@ fn whackmatch(
         }
         check -= 1;
 
-        candidate_offset = current_dict_position - last_dict_position;
+        candidate_offset = dict_offset(current_dict_position, last_dict_position);
         if candidate_offset <= last_candidate_offset || candidate_offset > WHACK_MAX_OFF {
             break;
         }
@@ -153,157 +194,164 @@ fn hashit(c: usize) -> u16 {
     ((((c & 0xffffff) * 0x6b43a9b5) >> (32 - HASH_LOG)) as u32 & HASH_MASK) as u16
 }
 
-/// Compress a section of data
-///
-/// lz77 compression with single lookup in a hash table for each block
-///
-/// Takes data in `src` and outputs a [`Vec<u8>`], updating
-/// [`Stats`] in `stats`
-///
-/// # Errors
-///
-/// If source is too small, compressed data is larger than
-/// source or likely to be so
-pub fn whack(w: &mut Whack, src: &[u8], stats: &mut Stats) -> Option<Vec<u8>> {
-    let mut current_source_position: usize;
-    let mut target_source_position: usize;
+/// compressor state carried from one token to the next
+struct State {
+    current_source_position: usize,
+    current_dict_position: u16,
+    cont: usize,
+    half: usize,
+    pending_output_bits: usize,
+    pending_output_bits_length: u16,
+    lithist: u32,
+    lits: usize,
+    matches: usize,
+    offbits: usize,
+    lenbits: usize,
+    dst: Vec<u8>,
+}
 
-    let mut half: usize;
-    let mut current_output_length: usize;
-
-    let mut cont: usize;
-    let mut pending_output_bits: usize;
-    let mut current_dict_position: u16;
-    let mut lithist: u32;
-    let mut pending_output_bits_length: u16;
-    let mut lits: usize;
-    let mut matches: usize;
-    let mut offbits: u16;
-    let mut lenbits: u16;
-    let max_source_position = src.len();
-    if max_source_position < MIN_MATCH {
-        return None;
+impl State {
+    /// start compressing `src`, which must hold at least `MIN_MATCH` bytes
+    fn new(w: &Whack, src: &[u8], half: usize) -> State {
+        State {
+            current_source_position: 0,
+            current_dict_position: w.begin,
+            cont: (((src[0] as u32) << 16) | ((src[1] as u32) << 8) | (src[2] as u32)) as usize,
+            half,
+            pending_output_bits: 0,
+            pending_output_bits_length: 0,
+            lithist: !(0),
+            lits: 0,
+            matches: 0,
+            offbits: 0,
+            lenbits: 0,
+            dst: Vec::with_capacity(src.len()),
+        }
+    }
+
+    /// move whole bytes from the bit accumulator to the output
+    ///
+    /// Fails if the output would grow to more than `max_output_length`.
+    fn flush(&mut self, max_output_length: usize) -> Result<(), Bail> {
+        while self.pending_output_bits_length >= 8 {
+            if self.dst.len() >= max_output_length {
+                return Err(Bail::Expansion);
+            }
+            let value = (self.pending_output_bits >> (self.pending_output_bits_length - 8)) as u8;
+            self.dst.push(value);
+            self.pending_output_bits_length -= 8;
+        }
+        Ok(())
     }
+}
+
+/// run the token loop over `src` until `stop` is reached
+///
+/// All of `src` is taken to be the block, for matching and hashing.
+/// Fails if compression should be abandoned.
+#[allow(clippy::disallowed_names)]
+fn compress(
+    w: &mut Whack,
+    s: &mut State,
+    src: &[u8],
+    stop: usize,
+    max_output_length: usize,
+) -> Result<(), Bail> {
+    let max_source_position = src.len();
+    let mut target_source_position: usize;
 
-    let mut dst = Vec::with_capacity(max_source_position);
-    current_output_length = 0;
-    let max_output_length: usize = max_source_position;
-    current_dict_position = w.begin;
-    current_source_position = 0;
-
-    cont = (((src[current_source_position] as u32) << 16)
-        | ((src[current_source_position + 1] as u32) << 8)
-        | (src[current_source_position + 2] as u32)) as usize;
-    half = max_source_position >> 1;
-    pending_output_bits_length = 0;
-    pending_output_bits = 0;
-    lits = 0;
-    matches = 0;
-    offbits = 0;
-    lenbits = 0;
-    lithist = !(0);
-    while current_source_position < max_source_position {
-        let mut hash = hashit(cont);
+    while s.current_source_position < stop {
+        let mut hash = hashit(s.cont);
         let mut match_len;
         let mut match_offset;
         let wmr = whackmatch(
             w,
             src,
-            current_source_position,
+            s.current_source_position,
             max_source_position,
             hash,
-            current_dict_position,
+            s.current_dict_position,
         );
         if let Some(foo) = wmr {
             (match_offset, match_len) = (foo.off, foo.len);
         } else {
             (match_offset, match_len) = (0, 0);
         }
-        target_source_position = current_source_position + match_len as usize;
+        target_source_position = s.current_source_position + match_len as usize;
 
         // flush pending bytes
-        while pending_output_bits_length >= 8 {
-            if current_output_length >= max_output_length {
-                // fail if output length exceeds source length
-                w.begin = current_dict_position;
-                return None;
-            }
-            let value = (pending_output_bits >> (pending_output_bits_length - 8)) as u8;
-            dst.push(value);
-            current_output_length += 1;
-            pending_output_bits_length -= 8;
-        }
+        // fail if output length exceeds source length
+        s.flush(max_output_length)?;
 
         if (match_len as usize) < MIN_MATCH {
-            let mut current_byte = src[current_source_position] as u16;
+            let mut current_byte = src[s.current_source_position] as u16;
             // append 1 if current byte is ASCII, else 0
-            lithist = lithist << 1
+            s.lithist = s.lithist << 1
                 | if !(32..=127).contains(&current_byte) {
                     1
                 } else {
                     0
                 };
 
-            if lithist & 0x1e != 0 {
+            if s.lithist & 0x1e != 0 {
                 // if previously any of the last 4 characters were not ASCII
                 // append byte extended by leading 0 bit
-                pending_output_bits = pending_output_bits << 9 | current_byte as usize;
-                pending_output_bits_length += 9;
-            } else if lithist & 1 != 0 {
+                s.pending_output_bits = s.pending_output_bits << 9 | current_byte as usize;
+                s.pending_output_bits_length += 9;
+            } else if s.lithist & 1 != 0 {
                 // if the current character was not ASCII, add 64
                 current_byte = (current_byte + 64) & 0xff;
                 if current_byte < 96 {
                     // if current character was < 32
                     // append new byte extended by two leading 0 bits
-                    pending_output_bits = pending_output_bits << 10 | current_byte as usize;
-                    pending_output_bits_length += 10;
+                    s.pending_output_bits = s.pending_output_bits << 10 | current_byte as usize;
+                    s.pending_output_bits_length += 10;
                 } else {
                     // append new byte extended by three leading 0 bits
-                    pending_output_bits = pending_output_bits << 11 | current_byte as usize;
-                    pending_output_bits_length += 11;
+                    s.pending_output_bits = s.pending_output_bits << 11 | current_byte as usize;
+                    s.pending_output_bits_length += 11;
                 }
             } else {
                 // if all of the last 5 characters were ASCII
                 // append byte
-                pending_output_bits = pending_output_bits << 8 | current_byte as usize;
-                pending_output_bits_length += 8;
+                s.pending_output_bits = s.pending_output_bits << 8 | current_byte as usize;
+                s.pending_output_bits_length += 8;
             }
-            lits += 1;
+            s.lits += 1;
 
             /*
              * speed hack
              * check for compression progress, bail if none achieved by halfway point
              */
-            if current_source_position > half {
-                if (4 * current_source_position) < (5 * lits) {
-                    w.begin = current_dict_position;
-                    return None;
+            if s.current_source_position > s.half {
+                if (4 * s.current_source_position) < (5 * s.lits) {
+                    return Err(Bail::Halfway);
                 }
-                half = max_source_position;
+                s.half = max_source_position;
             }
-            if current_source_position + MIN_MATCH <= max_source_position {
-                w.next[(current_dict_position & (WHACK_MAX_OFF - 1)) as usize] =
+            if s.current_source_position + MIN_MATCH <= max_source_position {
+                w.next[(s.current_dict_position & (WHACK_MAX_OFF - 1)) as usize] =
                     w.hash[hash as usize];
-                w.hash[hash as usize] = current_dict_position;
-                if current_source_position + MIN_MATCH < max_source_position {
-                    cont = cont << 8 | src[current_source_position + MIN_MATCH] as usize;
+                w.hash[hash as usize] = s.current_dict_position;
+                if s.current_source_position + MIN_MATCH < max_source_position {
+                    s.cont = s.cont << 8 | src[s.current_source_position + MIN_MATCH] as usize;
                 }
             }
-            current_dict_position = current_dict_position.wrapping_add(1);
-            current_source_position += 1;
+            s.current_dict_position = s.current_dict_position.wrapping_add(1);
+            s.current_source_position += 1;
         } else {
-            matches += 1;
+            s.matches += 1;
             if (match_len as usize) > MAXLEN {
                 match_len = MAXLEN as u16;
-                target_source_position = current_source_position + match_len as usize;
+                target_source_position = s.current_source_position + match_len as usize;
             }
             match_len -= MIN_MATCH as u16;
             if match_len < MAX_FAST_LEN as u16 {
                 let huff = &LENTAB[match_len as usize];
                 let bits = huff.bits;
-                pending_output_bits = pending_output_bits << bits | huff.encode;
-                pending_output_bits_length += bits;
-                lenbits += bits;
+                s.pending_output_bits = s.pending_output_bits << bits | huff.encode;
+                s.pending_output_bits_length += bits;
+                s.lenbits += bits as usize;
             } else {
                 let mut code = BIG_LEN_CODE as usize;
                 let mut bits = BIG_LEN_BITS as u16;
@@ -315,19 +363,11 @@ pub fn whack(w: &mut Whack, src: &[u8], stats: &mut Stats) -> Option<Vec<u8>> {
                     use_0 <<= bits & 1 ^ 1;
                     bits += 1;
                 }
-                pending_output_bits = pending_output_bits << bits | (code + match_len as usize);
-                pending_output_bits_length += bits;
-                lenbits += bits;
-                while pending_output_bits_length >= 8 {
-                    if current_output_length >= max_output_length {
-                        // fail if output length exceeds source length
-                        w.begin = current_dict_position;
-                        return None;
-                    }
-                    dst.push((pending_output_bits >> (pending_output_bits_length - 8)) as u8);
-                    current_output_length += 1;
-                    pending_output_bits_length -= 8;
-                }
+                s.pending_output_bits = s.pending_output_bits << bits | (code + match_len as usize);
+                s.pending_output_bits_length += bits;
+                s.lenbits += bits as usize;
+                // fail if output length exceeds source length
+                s.flush(max_output_length)?;
             }
 
             /*
@@ -339,44 +379,49 @@ pub fn whack(w: &mut Whack, src: &[u8], stats: &mut Stats) -> Option<Vec<u8>> {
                 bits += 1;
             }
             if bits < (MAX_OFF_BITS - 1) as u16 {
-                pending_output_bits =
-                    pending_output_bits << 3 | (bits - MIN_OFF_BITS as u16) as usize;
+                s.pending_output_bits =
+                    s.pending_output_bits << 3 | (bits - MIN_OFF_BITS as u16) as usize;
                 if bits != MIN_OFF_BITS as u16 {
                     bits -= 1;
                 }
-                pending_output_bits_length += bits + 3;
-                offbits += bits + 3;
+                s.pending_output_bits_length += bits + 3;
+                s.offbits += (bits + 3) as usize;
             } else {
-                pending_output_bits =
-                    pending_output_bits << 4 | 0xe | (bits - (MAX_OFF_BITS - 1) as u16) as usize;
+                s.pending_output_bits =
+                    s.pending_output_bits << 4 | 0xe | (bits - (MAX_OFF_BITS - 1) as u16) as usize;
                 bits -= 1;
-                pending_output_bits_length += bits + 4;
-                offbits += bits + 4;
+                s.pending_output_bits_length += bits + 4;
+                s.offbits += (bits + 4) as usize;
             }
-            pending_output_bits =
-                pending_output_bits << bits | (match_offset & (((1) << bits) - 1)) as usize;
-            while current_source_position != target_source_position {
-                if current_source_position + MIN_MATCH <= max_source_position {
-                    hash = hashit(cont);
-                    w.next[(current_dict_position & (WHACK_MAX_OFF - 1)) as usize] =
+            s.pending_output_bits =
+                s.pending_output_bits << bits | (match_offset & (((1) << bits) - 1)) as usize;
+            while s.current_source_position != target_source_position {
+                if s.current_source_position + MIN_MATCH <= max_source_position {
+                    hash = hashit(s.cont);
+                    w.next[(s.current_dict_position & (WHACK_MAX_OFF - 1)) as usize] =
                         w.hash[hash as usize];
-                    w.hash[hash as usize] = current_dict_position;
-                    if current_source_position + MIN_MATCH < max_source_position {
-                        cont = cont << 8 | src[current_source_position + MIN_MATCH] as usize;
+                    w.hash[hash as usize] = s.current_dict_position;
+                    if s.current_source_position + MIN_MATCH < max_source_position {
+                        s.cont = s.cont << 8 | src[s.current_source_position + MIN_MATCH] as usize;
                     }
                 }
-                current_dict_position = current_dict_position.wrapping_add(1);
-                current_source_position += 1;
+                s.current_dict_position = s.current_dict_position.wrapping_add(1);
+                s.current_source_position += 1;
             }
         }
     }
-    w.begin = current_dict_position;
+    Ok(())
+}
+
+/// account for a completed block and flush the last bits
+fn finish(mut s: State, src: &[u8], stats: &mut Stats) -> Result<Vec<u8>, Bail> {
+    let max_source_position = src.len();
+    let max_output_length = max_source_position;
     stats.statbytes += max_source_position;
-    stats.statlits += lits;
-    stats.statmatches += matches;
-    stats.statlitbits += current_output_length * 8 + pending_output_bits_length as usize
-        - offbits as usize
-        - lenbits as usize;
+    stats.statlits += s.lits;
+    stats.statmatches += s.matches;
+    stats.statlitbits +=
+        s.dst.len() * 8 + s.pending_output_bits_length as usize - s.offbits - s.lenbits;
     /*
         // XXXstroucki that -2 can cause the value to become negative.
         // Original C source returns overflowed nonsense.
@@ -384,26 +429,134 @@ pub fn whack(w: &mut Whack, src: &[u8], stats: &mut Stats) -> Option<Vec<u8>> {
         - offbits as usize
         - lenbits as usize;
         */
-    stats.statoffbits += offbits as usize;
-    stats.statlenbits += lenbits as usize;
+    stats.statoffbits += s.offbits;
+    stats.statlenbits += s.lenbits;
 
-    if pending_output_bits_length & 7 != 0 {
-        pending_output_bits <<= 8 - (pending_output_bits_length & 7);
-        pending_output_bits_length += 8 - (pending_output_bits_length & 7);
+    if s.pending_output_bits_length & 7 != 0 {
+        s.pending_output_bits <<= 8 - (s.pending_output_bits_length & 7);
+        s.pending_output_bits_length += 8 - (s.pending_output_bits_length & 7);
     }
-    while pending_output_bits_length >= 8 {
-        // fail if output length exceeds source length
-        if current_output_length >= max_output_length {
-            return None;
+    // fail if output length exceeds source length
+    s.flush(max_output_length)?;
+
+    stats.statoutbytes += s.dst.len();
+    //assert_eq!(wdst, dst.len());
+    Ok(s.dst)
+}
+
+/// Compress a section of data
+///
+/// lz77 compression with single lookup in a hash table for each block
+///
+/// Takes data in `src` and outputs a [`Vec<u8>`], updating
+/// [`Stats`] in `stats`
+///
+/// # Errors
+///
+/// If source is too small, compressed data is larger than
+/// source or likely to be so
+pub fn whack(w: &mut Whack, src: &[u8], stats: &mut Stats) -> Option<Vec<u8>> {
+    try_whack(w, src, stats).ok()
+}
+
+/// Compress a section of data, telling why it did not compress
+///
+/// As [`whack`], but returns the reason compression was abandoned.
+///
+/// # Errors
+///
+/// The [`Bail`] reason if the block was not compressed
+pub fn try_whack(w: &mut Whack, src: &[u8], stats: &mut Stats) -> Result<Vec<u8>, Bail> {
+    let max_source_position = src.len();
+    if max_source_position < MIN_MATCH {
+        return Err(Bail::TooSmall);
+    }
+
+    let mut s = State::new(w, src, max_source_position >> 1);
+    let done = compress(w, &mut s, src, max_source_position, max_source_position);
+    w.begin = s.current_dict_position;
+    done?;
+    finish(s, src, stats)
+}
+
+/// Incremental compressor
+///
+/// Compresses one block that is handed over in pieces, for example
+/// from scatter-gather I/O, and produces exactly the stream that
+/// [`whack`] would produce for the concatenated input. The hash
+/// chains, literal history and pending output bits are carried from
+/// one [`Encoder::write`] to the next.
+///
+/// Matches may extend up to `MAXLEN` bytes ahead and the bail-out
+/// check happens halfway through the block, so compression runs
+/// behind the input received so far and catches up in
+/// [`Encoder::finish`].
+pub struct Encoder {
+    w: Box<Whack>,
+    src: Vec<u8>,
+    /// present once compression has started
+    s: Option<State>,
+}
+
+impl Encoder {
+    /// Create an encoder with the given compression level, as for [`whackinit`]
+    pub fn new(level: u8) -> Encoder {
+        Encoder {
+            w: Box::new(whackinit(level)),
+            src: Vec::new(),
+            s: None,
         }
-        dst.push((pending_output_bits >> (pending_output_bits_length - 8)) as u8);
-        current_output_length += 1;
-        pending_output_bits_length -= 8;
     }
 
-    stats.statoutbytes += current_output_length;
-    //assert_eq!(wdst, dst.len());
-    Some(dst)
+    /// Add `data` to the block
+    pub fn write(&mut self, data: &[u8]) {
+        self.src.extend_from_slice(data);
+        let n = self.src.len();
+        // positions before the halfway point of what has been received
+        // are before the halfway point of the block, and everything a
+        // match there could look at is already present
+        let stop = (n.saturating_sub(MAXLEN + MIN_MATCH)).min((n >> 1) + 1);
+        if stop == 0 {
+            return;
+        }
+        let s = self
+            .s
+            .get_or_insert_with(|| State::new(&self.w, &self.src, usize::MAX));
+        // the halfway point and the output limit depend on the block
+        // size, so finish applies them and this cannot bail
+        compress(&mut self.w, s, &self.src, stop, usize::MAX)
+            .expect("no output limit before finish");
+    }
+
+    /// Compress the rest of the block and return the result
+    ///
+    /// # Errors
+    ///
+    /// If source is too small, compressed data is larger than
+    /// source or likely to be so
+    pub fn finish(mut self, stats: &mut Stats) -> Option<Vec<u8>> {
+        let max_source_position = self.src.len();
+        if max_source_position < MIN_MATCH {
+            return None;
+        }
+        let mut s = match self.s {
+            Some(s) => s,
+            None => State::new(&self.w, &self.src, 0),
+        };
+        s.half = max_source_position >> 1;
+        if s.dst.len() > max_source_position {
+            return None;
+        }
+        compress(
+            &mut self.w,
+            &mut s,
+            &self.src,
+            max_source_position,
+            max_source_position,
+        )
+        .ok()?;
+        finish(s, &self.src, stats).ok()
+    }
 }
 
 /// Compress a section of data
@@ -424,6 +577,6 @@ pub fn whackblock(src: &[u8]) -> Option<Vec<u8>> {
         statoffbits: 0,
         statlenbits: 0,
     };
-    let mut w = whackinit(6);
+    let mut w = whackinit(DEFAULT_LEVEL);
     whack(&mut w, src, &mut stats)
 }